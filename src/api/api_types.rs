@@ -2,8 +2,18 @@
 //!
 //! Defines API data models and helper methods.
 
-use chrono::Utc;
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::caching::history::CheckpointEntry;
+use crate::cron_tasks::runs::JobRun;
+use crate::cron_tasks::scheduler::JobState;
+use crate::env_config::ConfigValue;
+use crate::storage::download::DownloadOptions;
+use crate::storage::quota::QuotaStatus;
+use crate::storage::search_index::SearchHit;
+use crate::storage::FileInfo;
 
 /// Response structure for relaying a status and message back to the requester.
 #[derive(Serialize)]
@@ -18,43 +28,132 @@ pub struct LogListResponse {
     pub ok: bool,
     pub timestamp: String,
     pub log_files: Vec<String>,
+    pub files: Vec<FileInfo>,
+}
+
+/// Query parameters for filtering and sorting the list of log files.
+/// `sort` is one of `name` (default), `size`, `lines`, `created`, `modified`,
+/// `min_timestamp` or `max_timestamp` and `order` is either `asc` (default)
+/// or `desc`. Files are kept when their record timestamps overlap the `from`
+/// and `to` range.
+#[derive(Deserialize, Debug)]
+pub struct LogListParams {
+    pub prefix: Option<String>,
+    pub from: Option<String>,
+    pub to: Option<String>,
+    pub sort: Option<String>,
+    pub order: Option<String>,
 }
 
 /// Query parameters for requesting a paginated resource.
+/// `order` is either `asc` (default) or `desc` (newest first) and `tail`
+/// requests only the last lines instead of a page.
 #[derive(Deserialize, Debug)]
 pub struct PageParams {
     pub page: Option<u32>,
     pub page_size: Option<u32>,
+    pub order: Option<String>,
+    pub tail: Option<u32>,
 }
 
-/// Paginated resource response.
+/// Paginated resource response. The total is left out for tail and `desc`
+/// requests so the file doesn't have to be scanned to count its lines.
 #[derive(Serialize)]
 pub struct PagedLogContents {
     pub page: u32,
     pub page_size: u32,
-    pub total: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub total: Option<u32>,
     pub results: Vec<String>,
 }
 
+/// Query parameters for searching the contents of stored logs.
+#[derive(Deserialize, Debug)]
+pub struct SearchParams {
+    pub q: String,
+    pub from: Option<String>,
+    pub to: Option<String>,
+    pub limit: Option<usize>,
+}
+
+/// Response that contains the lines matching a search query.
+#[derive(Serialize)]
+pub struct SearchResponse {
+    pub ok: bool,
+    pub query: String,
+    pub total: usize,
+    pub results: Vec<SearchHit>,
+}
+
+/// Query parameters for limiting an export to a time range.
+#[derive(Deserialize, Debug)]
+pub struct TimeRangeParams {
+    pub from: Option<String>,
+    pub to: Option<String>,
+}
+
+impl TimeRangeParams {
+    /// Parses the range into milliseconds. Errors if either bound is invalid.
+    pub fn to_millis(&self) -> Result<(Option<i64>, Option<i64>), String> {
+        let parse = |value: &Option<String>| match value.as_deref() {
+            None => Ok(None),
+            Some(v) => parse_timestamp_param(v)
+                .map(Some)
+                .ok_or(format!("Invalid timestamp '{v}'")),
+        };
+        Ok((parse(&self.from)?, parse(&self.to)?))
+    }
+}
+
+/// Query parameters for downloading logs, optionally limited to a time range
+/// and gzip-compressed.
+#[derive(Deserialize, Debug)]
+pub struct DownloadParams {
+    pub from: Option<String>,
+    pub to: Option<String>,
+    pub gzip: Option<bool>,
+}
+
+impl DownloadParams {
+    /// Parses the parameters into download options. Errors if either bound is invalid.
+    pub fn to_options(&self) -> Result<DownloadOptions, String> {
+        let range = TimeRangeParams {
+            from: self.from.clone(),
+            to: self.to.clone(),
+        };
+        let (from, to) = range.to_millis()?;
+        Ok(DownloadOptions {
+            from,
+            to,
+            gzip: self.gzip.unwrap_or(false),
+        })
+    }
+}
+
+/// Parses a timestamp query parameter given either in milliseconds since the
+/// epoch or as an RFC 3339 date-time. Returns the timestamp in milliseconds.
+pub fn parse_timestamp_param(value: &str) -> Option<i64> {
+    match value.parse::<i64>() {
+        Ok(millis) => Some(millis),
+        Err(_) => DateTime::parse_from_rfc3339(value)
+            .ok()
+            .map(|d| d.timestamp_millis()),
+    }
+}
+
 impl LogListResponse {
-    /// Create a new LogListResponse with given logs.
-    pub fn new(logs: Vec<String>) -> LogListResponse {
+    /// Create a new LogListResponse with given log files.
+    pub fn new(files: Vec<FileInfo>) -> LogListResponse {
         LogListResponse {
             ok: true,
-            log_files: logs,
+            log_files: files.iter().map(|f| f.name.clone()).collect(),
+            files,
             timestamp: Utc::now().to_rfc3339(),
         }
     }
 }
 
 impl SimpleResponse {
-    /// Creates a SimpleResponse struct with default values (ok=true, message="success").
-    pub fn new() -> SimpleResponse {
-        SimpleResponse {
-            ok: true,
-            message: "success".to_owned(),
-        }
-    }
     /// Creates a SimpleResponse struct with given status and message.
     pub fn from(ok_status: bool, resp_msg: &str) -> SimpleResponse {
         SimpleResponse {
@@ -64,6 +163,26 @@ impl SimpleResponse {
     }
 }
 
+/// Health check response including the state of the storage limits.
+#[derive(Serialize)]
+pub struct HealthResponse {
+    pub ok: bool,
+    pub message: String,
+    pub storage: QuotaStatus,
+    /// Whether this instance runs the scheduled jobs (see `LS_LEADER_ELECTION`).
+    pub leader: bool,
+}
+
+/// Readiness check response with the state of the dependencies.
+#[derive(Serialize)]
+pub struct ReadyResponse {
+    pub ok: bool,
+    pub message: String,
+    /// `ok` or the reason Redis can't be reached (omitted when Redis isn't used).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub redis: Option<String>,
+}
+
 /// A response structure that contains the current version of `log-scraper`.
 #[derive(Serialize)]
 pub struct VersionResponse {
@@ -76,3 +195,110 @@ impl VersionResponse {
         VersionResponse { version: s }
     }
 }
+
+/// Response with the current watermark and the recorded changes (newest first).
+#[derive(Serialize)]
+pub struct CheckpointResponse {
+    pub ok: bool,
+    pub watermark: String,
+    pub history: Vec<CheckpointEntry>,
+}
+
+/// Query parameters for limiting the returned checkpoint history.
+#[derive(Deserialize, Debug)]
+pub struct CheckpointParams {
+    pub limit: Option<usize>,
+}
+
+/// Request body for setting the watermark, given in milliseconds since the
+/// epoch or as an RFC 3339 date-time.
+#[derive(Deserialize, Debug)]
+pub struct SetCheckpointBody {
+    pub watermark: String,
+}
+
+/// Request body for rewinding the watermark, either by a number of recorded
+/// syncs (`steps`) or to an earlier timestamp (`to`, milliseconds or RFC 3339).
+#[derive(Deserialize, Debug)]
+pub struct RewindCheckpointBody {
+    pub steps: Option<usize>,
+    pub to: Option<String>,
+}
+
+/// Response with the recorded change after setting or rewinding the watermark.
+#[derive(Serialize)]
+pub struct CheckpointUpdateResponse {
+    pub ok: bool,
+    pub checkpoint: CheckpointEntry,
+}
+
+/// Response with the result of a sync run.
+#[derive(Serialize)]
+pub struct SyncResponse {
+    pub ok: bool,
+    pub message: String,
+    pub run: JobRun,
+}
+
+/// Query parameters for filtering the recorded job runs by `job` name
+/// (i.e. `sync` or `archive`) and limiting their number.
+#[derive(Deserialize, Debug)]
+pub struct JobListParams {
+    pub job: Option<String>,
+    pub limit: Option<usize>,
+}
+
+/// Response with the recorded job runs (newest first).
+#[derive(Serialize)]
+pub struct JobListResponse {
+    pub ok: bool,
+    pub runs: Vec<JobRun>,
+}
+
+/// Response with a single recorded job run.
+#[derive(Serialize)]
+pub struct JobRunResponse {
+    pub ok: bool,
+    pub run: JobRun,
+}
+
+/// Response with the state of the scheduled jobs.
+#[derive(Serialize)]
+pub struct JobStateListResponse {
+    pub ok: bool,
+    pub jobs: Vec<JobState>,
+}
+
+/// Response with the state of a scheduled job.
+#[derive(Serialize)]
+pub struct JobStateResponse {
+    pub ok: bool,
+    pub job: JobState,
+}
+
+/// Request body for moving a job to another cron schedule.
+#[derive(Deserialize, Debug)]
+pub struct RescheduleJobBody {
+    pub schedule: String,
+}
+
+/// Response with the effective config values by name (secrets redacted).
+#[derive(Serialize)]
+pub struct ConfigResponse {
+    pub ok: bool,
+    pub config: BTreeMap<String, ConfigValue>,
+}
+
+/// Response of a config reload, with the changed values or why the new
+/// config was rejected.
+#[derive(Serialize)]
+pub struct ConfigReloadResponse {
+    pub ok: bool,
+    pub message: String,
+    /// The values changed and now in effect.
+    pub changed: Vec<String>,
+    /// The values changed but only applied on restart.
+    pub restart_required: Vec<String>,
+    /// The invalid values when the config was rejected.
+    pub errors: Vec<String>,
+}
@@ -0,0 +1,223 @@
+//! # Checkpoints Api module
+//!
+//! Provides endpoints for inspecting and moving the sync watermark (the
+//! timestamp the next sync fetches logs from).
+//!
+//! ## get_checkpoints_endpoint
+//!
+//! Responds with the current watermark and its recorded changes, newest
+//! first (see `LS_CHECKPOINT_HISTORY`). An empty watermark means the next
+//! sync fetches the logs of the past 7 days.
+//!
+//! GET `http://localhost:3333/checkpoints?limit=2`
+//!
+//! ```
+//! {
+//!     "ok": true,
+//!     "watermark": "1672617600001",
+//!     "history": [
+//!       {
+//!         "watermark": "1672617600001",
+//!         "previous": "1672617300001",
+//!         "recorded_at": "2023-01-02T00:05:00.000000Z",
+//!         "reason": "sync",
+//!         "batch_size": 42,
+//!         "files": ["app_2023-01-01.log", "app_2023-01-02.log"]
+//!       },
+//!       ...
+//!     ]
+//! }
+//! ```
+//!
+//! ## set_checkpoint_endpoint
+//!
+//! Sets the watermark to the given timestamp (milliseconds or RFC 3339),
+//! which can't be in the future. Waits for a running sync to finish and
+//! responds with `409 Conflict` when another instance is syncing.
+//!
+//! PUT `http://localhost:3333/checkpoints`
+//!
+//! ```
+//! { "watermark": "2023-01-01T00:00:00Z" }
+//! ```
+//!
+//! ```
+//! {
+//!     "ok": true,
+//!     "checkpoint": {
+//!       "watermark": "1672531200000",
+//!       "previous": "1672617600001",
+//!       "recorded_at": "2023-01-02T00:07:00.000000Z",
+//!       "reason": "set",
+//!       "batch_size": 0,
+//!       "files": []
+//!     }
+//! }
+//! ```
+//!
+//! ## rewind_checkpoint_endpoint
+//!
+//! Moves the watermark back, either to where it was before the given number
+//! of recorded syncs (`steps`) or to an earlier timestamp (`to`), so the
+//! logs since are fetched again. Responds like `set_checkpoint_endpoint`.
+//!
+//! The watermark can't be rewound past the oldest watermark recorded in the
+//! history (or the current one), nor to an empty watermark (7 days ago).
+//! Fetched logs are appended as usual, so the lines stored since the
+//! rewound-to watermark are stored again (no deduplication happens).
+//!
+//! POST `http://localhost:3333/checkpoints/rewind`
+//!
+//! ```
+//! { "steps": 2 }
+//! ```
+
+use actix_web::{
+    get, post, put,
+    web::{Data, Json, Query},
+    HttpResponse, Responder,
+};
+use chrono::Utc;
+use tracing::{event, instrument, Level};
+
+use crate::{
+    api::api_types::{
+        parse_timestamp_param, CheckpointParams, CheckpointResponse, CheckpointUpdateResponse,
+        RewindCheckpointBody, SetCheckpointBody, SimpleResponse,
+    },
+    caching, scraper, LogScraperState,
+};
+
+fn bad_request(message: &str) -> HttpResponse {
+    HttpResponse::BadRequest().json(SimpleResponse::from(false, message))
+}
+
+/// Returns the watermark the next sync starts from, preferring the one in
+/// memory unless a sync is updating it.
+async fn current_watermark(app_state: &LogScraperState) -> String {
+    if let Ok(last_seen) = app_state.last_seen.try_lock() {
+        if !last_seen.is_empty() {
+            return last_seen.clone();
+        }
+    }
+    caching::get_cached_val().await.unwrap_or_default()
+}
+
+/// Sets the watermark and responds with the recorded change.
+async fn update_watermark(
+    app_state: Data<LogScraperState>,
+    watermark: String,
+    reason: &str,
+) -> HttpResponse {
+    match scraper::set_watermark(app_state, watermark, reason).await {
+        Ok(checkpoint) => HttpResponse::Ok().json(CheckpointUpdateResponse {
+            ok: true,
+            checkpoint,
+        }),
+        Err(err) if err.starts_with("Another instance") => {
+            HttpResponse::Conflict().json(SimpleResponse::from(false, &err))
+        }
+        Err(err) => {
+            event!(Level::ERROR, "Unable to set the watermark: {err}");
+            HttpResponse::InternalServerError().json(SimpleResponse::from(
+                false,
+                "Error occurred while setting the watermark",
+            ))
+        }
+    }
+}
+
+/// Responds with the current watermark and its history.
+#[get("")]
+pub async fn get_checkpoints_endpoint(
+    app_state: Data<LogScraperState>,
+    params: Query<CheckpointParams>,
+) -> impl Responder {
+    let watermark = current_watermark(&app_state).await;
+    match caching::history::list(params.limit).await {
+        Ok(history) => HttpResponse::Ok().json(CheckpointResponse {
+            ok: true,
+            watermark,
+            history,
+        }),
+        Err(err) => {
+            event!(Level::ERROR, "Unable to read the checkpoint history: {err}");
+            HttpResponse::InternalServerError().json(SimpleResponse::from(
+                false,
+                "Error occurred while reading the checkpoint history",
+            ))
+        }
+    }
+}
+
+/// Sets the watermark to the given timestamp.
+#[put("")]
+#[instrument(name = "set_checkpoint_endpoint", skip(app_state))]
+pub async fn set_checkpoint_endpoint(
+    app_state: Data<LogScraperState>,
+    body: Json<SetCheckpointBody>,
+) -> impl Responder {
+    let Some(millis) = parse_timestamp_param(&body.watermark) else {
+        return bad_request("Invalid watermark, expected milliseconds or RFC 3339.");
+    };
+    if millis > Utc::now().timestamp_millis() {
+        return bad_request("The watermark can't be in the future.");
+    }
+    update_watermark(app_state, millis.to_string(), "set").await
+}
+
+/// Moves the watermark back by a number of syncs or to an earlier timestamp.
+#[post("/rewind")]
+#[instrument(name = "rewind_checkpoint_endpoint", skip(app_state))]
+pub async fn rewind_checkpoint_endpoint(
+    app_state: Data<LogScraperState>,
+    body: Json<RewindCheckpointBody>,
+) -> impl Responder {
+    let history = match caching::history::list(None).await {
+        Ok(history) => history,
+        Err(err) => {
+            event!(Level::ERROR, "Unable to read the checkpoint history: {err}");
+            return HttpResponse::InternalServerError().json(SimpleResponse::from(
+                false,
+                "Error occurred while reading the checkpoint history",
+            ));
+        }
+    };
+    let current = current_watermark(&app_state).await;
+
+    let watermark = match (body.steps, body.to.as_deref()) {
+        (Some(steps), None) if steps > 0 => {
+            match history.iter().filter(|e| e.reason == "sync").nth(steps - 1) {
+                Some(entry) => entry.previous.clone(),
+                None => return bad_request("Not enough syncs recorded to rewind that far."),
+            }
+        }
+        (None, Some(to)) => {
+            let Some(millis) = parse_timestamp_param(to) else {
+                return bad_request("Invalid value for to, expected milliseconds or RFC 3339.");
+            };
+            if current
+                .parse::<i64>()
+                .is_ok_and(|current| millis >= current)
+            {
+                return bad_request("Can only rewind to a timestamp before the current watermark.");
+            }
+            millis.to_string()
+        }
+        _ => return bad_request("Expected either a positive number of steps or a to timestamp."),
+    };
+
+    // logs before the oldest recorded watermark may not have been fetched by
+    // this service at all, so don't go back further than that
+    let oldest = caching::history::oldest_watermark(&history, &current);
+    match (oldest, watermark.parse::<i64>()) {
+        (Some(oldest), Ok(millis)) if millis >= oldest => {}
+        (Some(oldest), _) => {
+            return bad_request(&format!(
+                "Can't rewind past the oldest recorded watermark ({oldest})."
+            ))
+        }
+        (None, _) => return bad_request("No watermark is recorded to rewind from."),
+    }
+    update_watermark(app_state, watermark, "rewind").await
+}
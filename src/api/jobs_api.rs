@@ -0,0 +1,254 @@
+//! # Jobs Api module
+//!
+//! Provides endpoints for inspecting the runs of the scheduled jobs (see
+//! `LS_JOB_HISTORY`) and for controlling the jobs at runtime.
+//!
+//! ## get_jobs_endpoint
+//!
+//! Responds with the recorded runs, newest first, optionally filtered by
+//! `job` (`sync` or `archive`) and limited to a number of runs.
+//!
+//! GET `http://localhost:3333/jobs?job=sync&limit=1`
+//!
+//! ```
+//! {
+//!     "ok": true,
+//!     "runs": [
+//!       {
+//!         "id": 7,
+//!         "job": "sync",
+//!         "trigger": "schedule",
+//!         "started_at": "2023-01-02T00:06:00.000000Z",
+//!         "finished_at": "2023-01-02T00:06:02.000000Z",
+//!         "status": "succeeded",
+//!         "message": "Sync Complete!",
+//!         "sync": {
+//!           "skipped": false,
+//!           "logs_fetched": 42,
+//!           "hit_limit": false,
+//!           "files": ["app_2023-01-02.log"],
+//!           "watermark_before": "1672617300001",
+//!           "watermark_after": "1672617600001",
+//!           "error": null
+//!         }
+//!       }
+//!     ]
+//! }
+//! ```
+//!
+//! A run's `status` is one of `running`, `succeeded`, `failed` or `skipped`.
+//!
+//! ## get_job_endpoint
+//!
+//! Responds with a single recorded run, or `404 Not Found` when there is
+//! no run with that id (anymore).
+//!
+//! GET `http://localhost:3333/jobs/7`
+//!
+//! ```
+//! {
+//!     "ok": true,
+//!     "run": { "id": 7, "job": "sync", ... }
+//! }
+//! ```
+//!
+//! ## get_schedules_endpoint
+//!
+//! Responds with the state of the scheduled jobs.
+//!
+//! GET `http://localhost:3333/jobs/schedules`
+//!
+//! ```
+//! {
+//!     "ok": true,
+//!     "jobs": [
+//!       {
+//!         "name": "sync",
+//!         "schedule": "0 1/5 * * * *",
+//!         "paused": false,
+//!         "running": false,
+//!         "next_run": "2023-01-02T00:11:00Z"
+//!       }
+//!     ]
+//! }
+//! ```
+//!
+//! ## get_schedule_endpoint
+//!
+//! Responds with the state of a single job, or `404 Not Found` when no job
+//! with that name is scheduled.
+//!
+//! GET `http://localhost:3333/jobs/schedules/sync`
+//!
+//! ```
+//! {
+//!     "ok": true,
+//!     "job": { "name": "sync", "schedule": "0 1/5 * * * *", ... }
+//! }
+//! ```
+//!
+//! ## pause_job_endpoint / resume_job_endpoint
+//!
+//! Skips the scheduled runs of a job until it's resumed (on this instance,
+//! until it restarts). A run in progress is left to finish. Both respond
+//! like `get_schedule_endpoint`.
+//!
+//! POST `http://localhost:3333/jobs/schedules/sync/pause`
+//!
+//! POST `http://localhost:3333/jobs/schedules/sync/resume`
+//!
+//! ## reschedule_job_endpoint
+//!
+//! Moves a job to another cron schedule, responding with `400 Bad Request`
+//! for invalid expressions.
+//!
+//! PUT `http://localhost:3333/jobs/schedules/sync`
+//!
+//! ```
+//! { "schedule": "0 */15 * * * *" }
+//! ```
+//!
+//! ## trigger_job_endpoint
+//!
+//! Runs a job right away (even when paused or on a follower) and responds
+//! with the recorded run once it finished, or with `409 Conflict` when the
+//! job is already running.
+//!
+//! POST `http://localhost:3333/jobs/schedules/archive/trigger`
+//!
+//! ```
+//! {
+//!     "ok": true,
+//!     "run": { "id": 8, "job": "archive", "trigger": "api", ... }
+//! }
+//! ```
+
+use actix_web::{
+    get, post, put,
+    web::{Data, Json, Path, Query},
+    HttpResponse, Responder,
+};
+use std::sync::Arc;
+use tracing::instrument;
+
+use crate::{
+    api::api_types::{
+        JobListParams, JobListResponse, JobRunResponse, JobStateListResponse, JobStateResponse,
+        RescheduleJobBody, SimpleResponse,
+    },
+    cron_tasks::{
+        runs,
+        scheduler::{JobState, ScheduledJob, Scheduler},
+    },
+};
+
+/// Looks up the scheduled job, responding with `404 Not Found` when missing.
+fn find_job(scheduler: &Scheduler, name: &str) -> Result<Arc<ScheduledJob>, HttpResponse> {
+    scheduler.get(name).ok_or_else(|| {
+        HttpResponse::NotFound().json(SimpleResponse::from(
+            false,
+            &format!("No job named '{name}'"),
+        ))
+    })
+}
+
+fn job_state_response(job: JobState) -> HttpResponse {
+    HttpResponse::Ok().json(JobStateResponse { ok: true, job })
+}
+
+/// Responds with the recorded job runs.
+#[get("")]
+pub async fn get_jobs_endpoint(params: Query<JobListParams>) -> impl Responder {
+    HttpResponse::Ok().json(JobListResponse {
+        ok: true,
+        runs: runs::list(params.job.as_deref(), params.limit),
+    })
+}
+
+/// Responds with the state of the scheduled jobs.
+#[get("/schedules")]
+pub async fn get_schedules_endpoint(scheduler: Data<Scheduler>) -> impl Responder {
+    HttpResponse::Ok().json(JobStateListResponse {
+        ok: true,
+        jobs: scheduler.states(),
+    })
+}
+
+/// Responds with the state of the job with the given name.
+#[get("/schedules/{name}")]
+pub async fn get_schedule_endpoint(
+    scheduler: Data<Scheduler>,
+    name: Path<String>,
+) -> impl Responder {
+    match find_job(&scheduler, &name) {
+        Ok(job) => job_state_response(job.state()),
+        Err(response) => response,
+    }
+}
+
+/// Pauses the scheduled runs of the job.
+#[post("/schedules/{name}/pause")]
+#[instrument(name = "pause_job_endpoint", skip(scheduler))]
+pub async fn pause_job_endpoint(scheduler: Data<Scheduler>, name: Path<String>) -> impl Responder {
+    match find_job(&scheduler, &name) {
+        Ok(job) => job_state_response(job.pause()),
+        Err(response) => response,
+    }
+}
+
+/// Resumes the scheduled runs of the job.
+#[post("/schedules/{name}/resume")]
+#[instrument(name = "resume_job_endpoint", skip(scheduler))]
+pub async fn resume_job_endpoint(scheduler: Data<Scheduler>, name: Path<String>) -> impl Responder {
+    match find_job(&scheduler, &name) {
+        Ok(job) => job_state_response(job.resume()),
+        Err(response) => response,
+    }
+}
+
+/// Moves the job to another cron schedule.
+#[put("/schedules/{name}")]
+#[instrument(name = "reschedule_job_endpoint", skip(scheduler))]
+pub async fn reschedule_job_endpoint(
+    scheduler: Data<Scheduler>,
+    name: Path<String>,
+    body: Json<RescheduleJobBody>,
+) -> impl Responder {
+    let job = match find_job(&scheduler, &name) {
+        Ok(job) => job,
+        Err(response) => return response,
+    };
+    match job.reschedule(&body.schedule) {
+        Ok(state) => job_state_response(state),
+        Err(err) => HttpResponse::BadRequest().json(SimpleResponse::from(false, &err)),
+    }
+}
+
+/// Runs the job right away.
+#[post("/schedules/{name}/trigger")]
+#[instrument(name = "trigger_job_endpoint", skip(scheduler))]
+pub async fn trigger_job_endpoint(
+    scheduler: Data<Scheduler>,
+    name: Path<String>,
+) -> impl Responder {
+    let job = match find_job(&scheduler, &name) {
+        Ok(job) => job,
+        Err(response) => return response,
+    };
+    match job.trigger().await {
+        Some(run) => HttpResponse::Ok().json(JobRunResponse { ok: true, run }),
+        None => HttpResponse::Conflict().json(SimpleResponse::from(
+            false,
+            &format!("Job '{name}' is already running"),
+        )),
+    }
+}
+
+/// Responds with the job run with the given id.
+#[get("/{id}")]
+pub async fn get_job_endpoint(id: Path<u64>) -> impl Responder {
+    match runs::get(id.into_inner()) {
+        Some(run) => HttpResponse::Ok().json(JobRunResponse { ok: true, run }),
+        None => HttpResponse::NotFound().json(SimpleResponse::from(false, "Job run not found")),
+    }
+}
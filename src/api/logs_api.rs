@@ -15,14 +15,41 @@
 //!     "log_files": [
 //!       "app_2023-01-01.log",
 //!       "app_2023-01-02.log"
+//!     ],
+//!     "files": [
+//!       {
+//!         "name": "app_2023-01-01.log",
+//!         "size": 40960,
+//!         "lines": 1000,
+//!         "created": "2023-01-01T00:05:00Z",
+//!         "modified": "2023-01-01T23:55:00Z",
+//!         "min_timestamp": 1672531200000,
+//!         "max_timestamp": 1672617540000,
+//!         "compressed": false,
+//!         "archived": false,
+//!         "local": true,
+//!         "rollover": 0
+//!       },
+//!       ...
 //!     ]
 //! }
 //! ```
 //!
+//! The list can be filtered by a filename `prefix` and by a `from` and `to`
+//! time range (milliseconds or RFC 3339) that the records of a file overlap.
+//! It's sorted via `sort` (`name`, `size`, `lines`, `created`, `modified`,
+//! `min_timestamp` or `max_timestamp`) and `order` (`asc` or `desc`).
+//!
+//! GET `http://localhost:3333/logs/?prefix=my-project/&from=2023-01-01T00:00:00Z&sort=modified&order=desc`
+//!
+//! Files stored in partition folders (see `LOG_PATH_TEMPLATE`) are listed
+//! and addressed by their relative path, i.e. `my-project/error/app_2023-01-01.log`.
+//!
 //! ## get_log_contents_endpoint
 //!
 //! Attempts to read a log file's contents and return results
-//!  in a paged response structure.
+//!  in a paged response structure. Files that were archived and removed
+//!  from disk are fetched from the archive first.
 //!
 //! GET `http://localhost:3333/logs/app_2023-01-01.log?page=1&page_size=100`
 //!
@@ -38,6 +65,123 @@
 //! }
 //! ```
 //!
+//! Pages are read backward from the end of the file (newest lines first)
+//! with `order=desc`, leaving out the `total` as counting the lines would
+//! read the whole file. The last lines of a file can be requested with
+//! `tail=N` instead of a page, which returns them in file order (or newest
+//! first with `order=desc`) and leaves out the `total` as well.
+//!
+//! GET `http://localhost:3333/logs/app_2023-01-01.log?tail=2`
+//!
+//! ```
+//! {
+//!     "page": 1,
+//!     "page_size": 2,
+//!     "results": [
+//!       "<file-contents-from-second-to-last-line>",
+//!       "<file-contents-from-last-line>"
+//!     ]
+//! }
+//! ```
+//!
+//! ## search_logs_endpoint
+//!
+//! Searches the contents of stored logs and returns the matching lines with
+//! references to the file and line number they reside at. The optional `from`
+//! and `to` parameters (milliseconds or RFC 3339) limit results to a time range.
+//!
+//! GET `http://localhost:3333/logs/search?q=timeout&from=2023-01-01T00:00:00Z&limit=20`
+//!
+//! ```
+//! {
+//!     "ok": true,
+//!     "query": "timeout",
+//!     "total": 1,
+//!     "results": [
+//!       {
+//!         "filename": "app_2023-01-01.log",
+//!         "line": 42,
+//!         "timestamp": 1672531200000,
+//!         "content": "<file-contents-from-line-42>",
+//!         "highlight": "Request <b>timeout</b> after 30s"
+//!       }
+//!     ]
+//! }
+//! ```
+//!
+//! ## export_logs_endpoint
+//!
+//! Exports the records of all stored logs within the optional `from` and `to`
+//! time range (milliseconds or RFC 3339) as a Parquet file download.
+//!
+//! GET `http://localhost:3333/logs/export?from=2023-01-01T00:00:00Z&to=2023-01-02T00:00:00Z`
+//!
+//! ## export_log_endpoint
+//!
+//! Exports the records of a single log file as a Parquet file download. Also
+//! accepts the `from` and `to` parameters.
+//!
+//! GET `http://localhost:3333/logs/app_2023-01-01.log/export`
+//!
+//! ## download_log_endpoint
+//!
+//! Streams a whole log file as an attachment (one JSON record per line).
+//! The optional `from` and `to` parameters (milliseconds or RFC 3339) limit
+//! the lines to a time range and `gzip=true` compresses the download on the
+//! fly. Files that were archived and removed from disk are fetched from the
+//! archive first.
+//!
+//! GET `http://localhost:3333/logs/app_2023-01-01.log/download?gzip=true&from=2023-01-01T12:00:00Z`
+//!
+//! ## bundle_logs_endpoint
+//!
+//! Streams a tar archive holding every stored log file with lines in the
+//! optional `from` and `to` time range (only those lines are included).
+//! `gzip=true` responds with a `.tar.gz` instead.
+//!
+//! GET `http://localhost:3333/logs/bundle?from=2023-01-01T00:00:00Z&to=2023-01-08T00:00:00Z&gzip=true`
+//!
+//! ## verify_log_endpoint
+//!
+//! Verifies a log file against its hash chain and signed manifest, proving
+//! no line was altered, removed or added since it was scraped. For a deleted
+//! file the signed tombstone left in place of its chain is checked instead,
+//! reporting `deleted_at`.
+//!
+//! GET `http://localhost:3333/logs/app_2023-01-01.log/verify`
+//!
+//! ```
+//! {
+//!     "filename": "app_2023-01-01.log",
+//!     "ok": true,
+//!     "batches": 12,
+//!     "lines": 480,
+//!     "signature_valid": true,
+//!     "errors": []
+//! }
+//! ```
+//!
+//! ## sync_logs_endpoint
+//!
+//! Runs a sync right away and responds with the recorded run (see the jobs
+//! api), with `500 Internal Server Error` when the sync failed and `409
+//! Conflict` when a sync is already running.
+//!
+//! GET `http://localhost:3333/logs/sync`
+//!
+//! ```
+//! {
+//!     "ok": true,
+//!     "message": "Sync Complete!",
+//!     "run": {
+//!       "id": 7,
+//!       "job": "sync",
+//!       "trigger": "api",
+//!       ...
+//!     }
+//! }
+//! ```
+//!
 //! ## delete_log_endpoint
 //!
 //! Deletes the log file on disk and returns a success message.
@@ -53,42 +197,326 @@
 
 use actix_web::{
     delete, get,
+    http::header::{ContentDisposition, DispositionParam, DispositionType},
     web::{Data, Path, Query},
     HttpResponse, Responder,
 };
 use tracing::{event, instrument, Level};
 
 use crate::{
-    api::api_types::{LogListResponse, PageParams, PagedLogContents, SimpleResponse},
-    scraper, storage, LogScraperState,
+    api::api_types::{
+        parse_timestamp_param, DownloadParams, LogListParams, LogListResponse, PageParams,
+        PagedLogContents, SearchParams, SearchResponse, SimpleResponse, SyncResponse,
+        TimeRangeParams,
+    },
+    cron_tasks::{self, runs::JobStatus, scheduler::Scheduler},
+    storage,
 };
 
+/// Responds with a bad request for file ids that aren't safe to use.
+fn invalid_filename_response() -> HttpResponse {
+    HttpResponse::BadRequest().json(SimpleResponse::from(false, "Invalid file name"))
+}
+
 /// Attempts to add logs to the filesystem from a remote server.
-/// Fetches logs from remote server and saves them to disk.
+/// Fetches logs from remote server and saves them to disk, responding
+/// with the recorded run.
 #[get("/sync")]
-#[instrument(name = "sync_logs_endpoint")]
-pub async fn sync_logs_endpoint(app_state: Data<LogScraperState>) -> impl Responder {
-    match scraper::run_sync(app_state).await {
-        Ok(_) => event!(Level::INFO, "Sync Complete!"),
-        Err(err) => event!(
-            Level::ERROR,
-            "An error occurred while running the sync: {err:?}"
-        ),
-    };
-    HttpResponse::Ok().json(SimpleResponse::new())
+#[instrument(name = "sync_logs_endpoint", skip(scheduler))]
+pub async fn sync_logs_endpoint(scheduler: Data<Scheduler>) -> impl Responder {
+    let Some(job) = scheduler.get(cron_tasks::SYNC_JOB) else {
+        return HttpResponse::NotFound()
+            .json(SimpleResponse::from(false, "The sync job isn't scheduled"));
+    };
+    let Some(run) = job.trigger().await else {
+        return HttpResponse::Conflict()
+            .json(SimpleResponse::from(false, "A sync is already running"));
+    };
+    let response = SyncResponse {
+        ok: run.status != JobStatus::Failed,
+        message: run.message.clone(),
+        run,
+    };
+    match response.ok {
+        true => HttpResponse::Ok().json(response),
+        false => HttpResponse::InternalServerError().json(response),
+    }
 }
 
-/// Attempts to read the list of log filenames on disk and
-/// returns them.
+/// Attempts to read the list of log files with their metadata and
+/// returns them filtered and sorted by the query parameters.
 #[get("/")]
-pub async fn get_log_list_endpoint() -> impl Responder {
-    let resp = storage::get_log_filenames().await;
-    HttpResponse::Ok().json(LogListResponse::new(resp))
+pub async fn get_log_list_endpoint(params: Query<LogListParams>) -> impl Responder {
+    let range = TimeRangeParams {
+        from: params.from.clone(),
+        to: params.to.clone(),
+    };
+    let (from, to) = match range.to_millis() {
+        Ok(r) => r,
+        Err(err) => return HttpResponse::BadRequest().json(SimpleResponse::from(false, &err)),
+    };
+    let descending = match params.order.as_deref() {
+        None | Some("asc") => false,
+        Some("desc") => true,
+        Some(_) => {
+            return HttpResponse::BadRequest().json(SimpleResponse::from(
+                false,
+                "Invalid value for order parameter.",
+            ))
+        }
+    };
+
+    // archived files no longer on disk are included (see `get_file_infos`)
+    let mut files = storage::get_file_infos().await;
+    files.retain(|f| {
+        params
+            .prefix
+            .as_deref()
+            .is_none_or(|p| f.name.starts_with(p))
+            && from.is_none_or(|from| f.max_timestamp.is_none_or(|max| max >= from))
+            && to.is_none_or(|to| f.min_timestamp.is_none_or(|min| min <= to))
+    });
+
+    match params.sort.as_deref().unwrap_or("name") {
+        "name" => files.sort_by(|a, b| a.name.cmp(&b.name)),
+        "size" => files.sort_by_key(|f| f.size),
+        "lines" => files.sort_by_key(|f| f.lines),
+        "created" => files.sort_by_key(|f| f.created),
+        "modified" => files.sort_by_key(|f| f.modified),
+        "min_timestamp" => files.sort_by_key(|f| f.min_timestamp),
+        "max_timestamp" => files.sort_by_key(|f| f.max_timestamp),
+        _ => {
+            return HttpResponse::BadRequest().json(SimpleResponse::from(
+                false,
+                "Invalid value for sort parameter.",
+            ))
+        }
+    }
+    if descending {
+        files.reverse();
+    }
+    HttpResponse::Ok().json(LogListResponse::new(files))
+}
+
+/// Searches the contents of stored logs via the full-text search index.
+#[get("/search")]
+#[instrument(name = "search_logs_endpoint")]
+pub async fn search_logs_endpoint(params: Query<SearchParams>) -> impl Responder {
+    if storage::search_index::global().is_none() {
+        return HttpResponse::NotFound().json(SimpleResponse::from(false, "Search is not enabled"));
+    }
+
+    if params.q.trim().is_empty() {
+        return HttpResponse::BadRequest().json(SimpleResponse::from(
+            false,
+            "Invalid value for q parameter.",
+        ));
+    }
+
+    // parse the optional time range
+    let from = params.from.as_deref().map(parse_timestamp_param);
+    let to = params.to.as_deref().map(parse_timestamp_param);
+    if matches!(from, Some(None)) || matches!(to, Some(None)) {
+        return HttpResponse::BadRequest().json(SimpleResponse::from(
+            false,
+            "Invalid value for from or to parameter.",
+        ));
+    }
+
+    let limit = params.limit.unwrap_or(100);
+    let query = params.q.clone();
+    let searched = storage::search_index::run(move |index| {
+        index.search(&query, from.flatten(), to.flatten(), limit)
+    });
+    match searched
+        .await
+        .unwrap_or(Err("Search is not enabled".to_owned()))
+    {
+        Ok((total, results)) => HttpResponse::Ok().json(SearchResponse {
+            ok: true,
+            query: params.q.clone(),
+            total,
+            results,
+        }),
+        Err(err) => {
+            event!(Level::ERROR, "Unable to search logs: {err}");
+            HttpResponse::BadRequest().json(SimpleResponse::from(false, &err))
+        }
+    }
+}
+
+/// Content type of Parquet file downloads.
+const PARQUET_CONTENT_TYPE: &str = "application/vnd.apache.parquet";
+
+/// Runs the Parquet export and responds with the resulting file as an attachment.
+async fn parquet_download(
+    filenames: &[String],
+    range: &TimeRangeParams,
+    name: &str,
+) -> HttpResponse {
+    let (from, to) = match range.to_millis() {
+        Ok(r) => r,
+        Err(err) => return HttpResponse::BadRequest().json(SimpleResponse::from(false, &err)),
+    };
+
+    match storage::parquet_export::export_parquet(filenames, from, to).await {
+        Ok(bytes) => HttpResponse::Ok()
+            .content_type(PARQUET_CONTENT_TYPE)
+            .insert_header(ContentDisposition {
+                disposition: DispositionType::Attachment,
+                parameters: vec![DispositionParam::Filename(format!("{name}.parquet"))],
+            })
+            .body(bytes),
+        Err(err) => {
+            event!(Level::ERROR, "Unable to export logs: {err}");
+            HttpResponse::InternalServerError().json(SimpleResponse::from(
+                false,
+                "Error occurred while exporting the logs",
+            ))
+        }
+    }
+}
+
+/// Exports the records of all stored logs in a time range as Parquet.
+#[get("/export")]
+#[instrument(name = "export_logs_endpoint")]
+pub async fn export_logs_endpoint(range: Query<TimeRangeParams>) -> impl Responder {
+    parquet_download(&[], &range, "logs").await
+}
+
+/// Exports the records of a single log file as Parquet.
+#[get("/{id:.*}/export")]
+#[instrument(name = "export_log_endpoint")]
+pub async fn export_log_endpoint(
+    id: Path<String>,
+    range: Query<TimeRangeParams>,
+) -> impl Responder {
+    let Some(sanitized) = storage::sanitize_filename(&id) else {
+        return invalid_filename_response();
+    };
+    if !storage::global().has_file(&sanitized).await {
+        event!(Level::ERROR, "Unable to find file with name {sanitized}");
+        return HttpResponse::NotFound().json(SimpleResponse::from(false, "Unable to find file"));
+    }
+    parquet_download(std::slice::from_ref(&sanitized), &range, &sanitized).await
+}
+
+/// Makes sure the file is available, fetching it from the archive when it
+/// was removed from disk. Returns the error response when it isn't.
+async fn ensure_file(filename: &str) -> Option<HttpResponse> {
+    if storage::global().has_file(filename).await {
+        return None;
+    }
+    match storage::archive::restore(filename).await {
+        Ok(true) => {
+            event!(Level::INFO, "Fetched {filename} from the archive");
+            None
+        }
+        Ok(false) => {
+            event!(Level::ERROR, "Unable to find file with name {filename}");
+            Some(HttpResponse::NotFound().json(SimpleResponse::from(false, "Unable to find file")))
+        }
+        Err(err) => {
+            event!(
+                Level::ERROR,
+                "Unable to fetch {filename} from the archive: {err}"
+            );
+            Some(
+                HttpResponse::InternalServerError().json(SimpleResponse::from(
+                    false,
+                    "Error occurred while fetching the file from the archive",
+                )),
+            )
+        }
+    }
+}
+
+/// Responds with a streamed attachment of the given name.
+fn attachment(name: String, content_type: &str) -> actix_web::HttpResponseBuilder {
+    let mut builder = HttpResponse::Ok();
+    builder
+        .content_type(content_type)
+        .insert_header(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename(name)],
+        });
+    builder
+}
+
+/// Content type of gzip-compressed downloads.
+const GZIP_CONTENT_TYPE: &str = "application/gzip";
+
+/// Streams a tar bundle of all stored logs in a time range.
+#[get("/bundle")]
+#[instrument(name = "bundle_logs_endpoint")]
+pub async fn bundle_logs_endpoint(params: Query<DownloadParams>) -> impl Responder {
+    let options = match params.to_options() {
+        Ok(o) => o,
+        Err(err) => return HttpResponse::BadRequest().json(SimpleResponse::from(false, &err)),
+    };
+
+    let mut filenames = storage::global().get_log_filenames().await;
+    filenames.sort();
+    let (name, content_type) = if options.gzip {
+        ("logs.tar.gz", GZIP_CONTENT_TYPE)
+    } else {
+        ("logs.tar", "application/x-tar")
+    };
+    attachment(name.to_owned(), content_type)
+        .streaming(storage::download::stream_bundle(filenames, options))
+}
+
+/// Streams the lines of a log file as a download.
+#[get("/{id:.*}/download")]
+#[instrument(name = "download_log_endpoint")]
+pub async fn download_log_endpoint(
+    id: Path<String>,
+    params: Query<DownloadParams>,
+) -> impl Responder {
+    let Some(sanitized) = storage::sanitize_filename(&id) else {
+        return invalid_filename_response();
+    };
+    let options = match params.to_options() {
+        Ok(o) => o,
+        Err(err) => return HttpResponse::BadRequest().json(SimpleResponse::from(false, &err)),
+    };
+    if let Some(response) = ensure_file(&sanitized).await {
+        return response;
+    }
+
+    // name the download after the file itself, without its partition folders
+    let basename = sanitized
+        .rsplit('/')
+        .next()
+        .unwrap_or(&sanitized)
+        .to_owned();
+    let (name, content_type) = if options.gzip {
+        (format!("{basename}.gz"), GZIP_CONTENT_TYPE)
+    } else {
+        (basename, "application/x-ndjson")
+    };
+    attachment(name, content_type).streaming(storage::download::stream_file(sanitized, options))
+}
+
+/// Verifies a log file against its hash chain.
+#[get("/{id:.*}/verify")]
+#[instrument(name = "verify_log_endpoint")]
+pub async fn verify_log_endpoint(id: Path<String>) -> impl Responder {
+    let Some(sanitized) = storage::sanitize_filename(&id) else {
+        return invalid_filename_response();
+    };
+    if !storage::global().has_file(&sanitized).await
+        && !storage::hash_chain::has_tombstone(&sanitized)
+    {
+        event!(Level::ERROR, "Unable to find file with name {sanitized}");
+        return HttpResponse::NotFound().json(SimpleResponse::from(false, "Unable to find file"));
+    }
+    HttpResponse::Ok().json(storage::hash_chain::verify(&sanitized).await)
 }
 
 /// Attempts to read a log file's contents and return results
 /// in a paged response structure.
-#[get("/{id}")]
+#[get("/{id:.*}")]
 #[instrument(name = "get_log_contents_endpoint")]
 pub async fn get_log_contents_endpoint(
     paging: Query<PageParams>,
@@ -97,7 +525,9 @@ pub async fn get_log_contents_endpoint(
     event!(Level::INFO, "Looking for file with name {id}");
 
     // sanitize id so they can't traverse directories
-    let sanitized = id.replace('/', "");
+    let Some(sanitized) = storage::sanitize_filename(&id) else {
+        return invalid_filename_response();
+    };
     let page = paging.page.unwrap_or(1);
     let page_size = paging.page_size.unwrap_or(100);
 
@@ -114,24 +544,64 @@ pub async fn get_log_contents_endpoint(
             "Invalid value for page_size parameter.",
         ));
     }
+    if paging.tail == Some(0) {
+        return HttpResponse::BadRequest().json(SimpleResponse::from(
+            false,
+            "Invalid value for tail parameter.",
+        ));
+    }
+    let descending = match paging.order.as_deref() {
+        None | Some("asc") => false,
+        Some("desc") => true,
+        Some(_) => {
+            return HttpResponse::BadRequest().json(SimpleResponse::from(
+                false,
+                "Invalid value for order parameter.",
+            ))
+        }
+    };
 
-    // validate file exists
-    if !storage::has_file(&sanitized) {
-        event!(Level::ERROR, "Unable to find file with name {sanitized}");
-        return HttpResponse::NotFound().json(SimpleResponse::from(false, "Unable to find file"));
+    // validate file exists (fetching it from the archive if needed)
+    if let Some(response) = ensure_file(&sanitized).await {
+        return response;
     }
+    let store = storage::global();
 
-    // read the contents of the file
-    match storage::total_lines(&sanitized).await {
-        Ok(total) => {
-            let lines = storage::get_lines_by_page(&sanitized, page, page_size).await;
-            HttpResponse::Ok().json(PagedLogContents {
-                total: total.try_into().unwrap_or(0),
-                results: lines,
-                page,
-                page_size,
+    // read the contents of the file (backward from the end for desc order and tail)
+    let contents = if let Some(tail) = paging.tail {
+        store
+            .get_last_lines(&sanitized, 0, tail as usize)
+            .await
+            .map(|mut lines| {
+                if !descending {
+                    lines.reverse();
+                }
+                (None, lines)
             })
+    } else if descending {
+        // counting the lines would scan the whole file, so the total is left out
+        let skip = (page - 1) as usize * page_size as usize;
+        store
+            .get_last_lines(&sanitized, skip, page_size as usize)
+            .await
+            .map(|lines| (None, lines))
+    } else {
+        match store.total_lines(&sanitized).await {
+            Ok(total) => store
+                .get_lines_by_page(&sanitized, page, page_size)
+                .await
+                .map(|lines| (Some(total), lines)),
+            Err(err) => Err(err),
         }
+    };
+
+    match contents {
+        Ok((total, lines)) => HttpResponse::Ok().json(PagedLogContents {
+            total: total.map(|t| t.try_into().unwrap_or(0)),
+            results: lines,
+            page: if paging.tail.is_some() { 1 } else { page },
+            page_size: paging.tail.unwrap_or(page_size),
+        }),
         Err(err) => {
             event!(
                 Level::ERROR,
@@ -146,14 +616,17 @@ pub async fn get_log_contents_endpoint(
 }
 
 /// Attempts to delete a log file.
-#[delete("/{id}")]
+#[delete("/{id:.*}")]
 #[instrument(name = "delete_log_endpoint")]
 pub async fn delete_log_endpoint(id: Path<String>) -> impl Responder {
     event!(Level::INFO, "Deleting log file with name {id}");
-    let sanitized = id.replace('/', "");
+    let Some(sanitized) = storage::sanitize_filename(&id) else {
+        return invalid_filename_response();
+    };
 
     // validate file exists
-    if !storage::has_file(&sanitized) {
+    let store = storage::global();
+    if !store.has_file(&sanitized).await {
         event!(Level::ERROR, "Unable to find file with name {sanitized}");
         return HttpResponse::NotFound().json(SimpleResponse::from(false, "Unable to find file"));
     }
@@ -5,9 +5,15 @@
 /// Module that defines API data structures.
 pub mod api_types;
 
+/// Module for the Checkpoints API endpoints.
+pub mod checkpoints_api;
+
 /// Module for API root endpoints.
 pub mod index_api;
 
+/// Module for the Jobs API endpoints.
+pub mod jobs_api;
+
 /// Module for the Logs API endpoints.
 pub mod logs_api;
 
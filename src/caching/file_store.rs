@@ -0,0 +1,88 @@
+//! # File Checkpoint Store Module
+//!
+//! Handles saving and reading checkpoints to and from a local file.
+//!
+//! ## Path
+//!
+//! caching/file_store.rs
+//!
+//! # Description
+//!
+//! Keeps every checkpoint in a JSON object keyed by name in the file set via
+//! `LS_CHECKPOINT_PATH` (`.checkpoints.json` under `LOG_DIRECTORY` by
+//! default). Updates are written to a temp file that then replaces the
+//! previous one, so a crash never leaves a partially written checkpoint.
+
+use async_trait::async_trait;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use tokio::sync::Mutex;
+
+use crate::caching::CheckpointStore;
+use crate::env_config::{EnvConfig, LOG_DIRECTORY, LS_CHECKPOINT_PATH};
+
+/// Filename of the checkpoints when `LS_CHECKPOINT_PATH` is not set.
+const DEFAULT_CHECKPOINT_NAME: &str = ".checkpoints.json";
+
+/// Checkpoint backend that keeps values in a local file.
+#[derive(Debug)]
+pub struct FileCheckpointStore {
+    path: PathBuf,
+    /// Serializes the read-modify-write of updates.
+    write_lock: Mutex<()>,
+}
+
+impl FileCheckpointStore {
+    /// Creates a new `FileCheckpointStore` struct using the configured location.
+    pub fn new() -> FileCheckpointStore {
+        let env = EnvConfig::global();
+        let configured = env.get_val(LS_CHECKPOINT_PATH);
+        let path = if configured.is_empty() {
+            Path::new(&env.get_val(LOG_DIRECTORY)).join(DEFAULT_CHECKPOINT_NAME)
+        } else {
+            PathBuf::from(configured)
+        };
+        FileCheckpointStore {
+            path,
+            write_lock: Mutex::new(()),
+        }
+    }
+
+    /// Reads all checkpoints (none if the file doesn't exist yet).
+    async fn read_all(&self) -> Result<BTreeMap<String, String>, String> {
+        match tokio::fs::read_to_string(&self.path).await {
+            Ok(contents) => serde_json::from_str(&contents)
+                .map_err(|e| format!("Unable to parse checkpoints in {:?}: {e:?}", self.path)),
+            Err(err) if err.kind() == tokio::io::ErrorKind::NotFound => Ok(BTreeMap::new()),
+            Err(err) => Err(format!(
+                "Unable to read checkpoints from {:?}: {err:?}",
+                self.path
+            )),
+        }
+    }
+}
+
+#[async_trait]
+impl CheckpointStore for FileCheckpointStore {
+    async fn get(&self, key: &str) -> Result<String, String> {
+        Ok(self.read_all().await?.remove(key).unwrap_or_default())
+    }
+
+    async fn set(&self, key: &str, val: String) -> Result<(), String> {
+        let _guard = self.write_lock.lock().await;
+        let mut checkpoints = self.read_all().await?;
+        checkpoints.insert(key.to_owned(), val);
+
+        let write = async {
+            if let Some(parent) = self.path.parent() {
+                tokio::fs::create_dir_all(parent).await?;
+            }
+            let tmp_path = self.path.with_extension("json.tmp");
+            tokio::fs::write(&tmp_path, serde_json::to_string_pretty(&checkpoints)?).await?;
+            tokio::fs::rename(&tmp_path, &self.path).await
+        };
+        write
+            .await
+            .map_err(|e| format!("Unable to write checkpoints to {:?}: {e:?}", self.path))
+    }
+}
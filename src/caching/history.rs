@@ -0,0 +1,129 @@
+//! # Checkpoint History Module
+//!
+//! Keeps a record of past watermark values.
+//!
+//! ## Path
+//!
+//! caching/history.rs
+//!
+//! # Description
+//!
+//! Every change of the watermark (by a sync that wrote logs, or set and
+//! rewound via the API) is recorded with the previous value, the size of
+//! the batch and the files written. Entries are stored newest first as a
+//! JSON list under `{REDIS_KEY_NAME}:history` in the configured checkpoint
+//! backend and capped at `LS_CHECKPOINT_HISTORY` entries.
+
+use chrono::{DateTime, Utc};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+
+use crate::caching;
+use crate::env_config::{EnvConfig, LS_CHECKPOINT_HISTORY, REDIS_KEY_NAME};
+
+/// Serializes the read-modify-write of the history within this process.
+static HISTORY_LOCK: Lazy<Mutex<()>> = Lazy::new(|| Mutex::new(()));
+
+/// A recorded change of the watermark.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CheckpointEntry {
+    /// The watermark after the change (empty meaning 7 days ago).
+    pub watermark: String,
+    /// The watermark before the change.
+    pub previous: String,
+    pub recorded_at: DateTime<Utc>,
+    /// What changed the watermark: `sync`, `set` or `rewind`.
+    pub reason: String,
+    /// Number of log lines written by the sync.
+    pub batch_size: usize,
+    /// Files the sync wrote to.
+    pub files: Vec<String>,
+}
+
+impl CheckpointEntry {
+    /// Creates a new `CheckpointEntry` struct recorded now.
+    pub fn new(reason: &str, previous: String, watermark: String) -> CheckpointEntry {
+        CheckpointEntry {
+            watermark,
+            previous,
+            recorded_at: Utc::now(),
+            reason: reason.to_owned(),
+            batch_size: 0,
+            files: vec![],
+        }
+    }
+}
+
+/// Returns the oldest non-empty watermark among the entries and the current
+/// watermark, the furthest back the watermark can be rewound to. Empty
+/// watermarks (7 days ago) aren't a bound.
+pub fn oldest_watermark(entries: &[CheckpointEntry], current: &str) -> Option<i64> {
+    entries
+        .iter()
+        .flat_map(|e| [e.watermark.as_str(), e.previous.as_str()])
+        .chain([current])
+        .filter_map(|watermark| watermark.parse::<i64>().ok())
+        .min()
+}
+
+fn get_history_key() -> String {
+    format!("{}:history", EnvConfig::global().get_val(REDIS_KEY_NAME))
+}
+
+async fn read_history() -> Result<Vec<CheckpointEntry>, String> {
+    let stored = caching::global().get(&get_history_key()).await?;
+    if stored.is_empty() {
+        return Ok(vec![]);
+    }
+    serde_json::from_str(&stored).map_err(|e| format!("Unable to parse checkpoint history: {e:?}"))
+}
+
+/// Returns the recorded entries, newest first.
+pub async fn list(limit: Option<usize>) -> Result<Vec<CheckpointEntry>, String> {
+    let mut entries = read_history().await?;
+    if let Some(limit) = limit {
+        entries.truncate(limit);
+    }
+    Ok(entries)
+}
+
+/// Adds the entry to the history, dropping the oldest ones past `LS_CHECKPOINT_HISTORY`.
+/// Nothing is written when the stored history can't be read, so a failed
+/// read never replaces it.
+pub async fn record(entry: CheckpointEntry) -> Result<(), String> {
+    let max_entries = EnvConfig::global()
+        .get_val(LS_CHECKPOINT_HISTORY)
+        .parse::<usize>()
+        .unwrap_or(100);
+    if max_entries == 0 {
+        return Ok(());
+    }
+
+    let _guard = HISTORY_LOCK.lock().await;
+    let mut entries = read_history()
+        .await
+        .map_err(|e| format!("Not recording checkpoint history: {e}"))?;
+    entries.insert(0, entry);
+    entries.truncate(max_entries);
+
+    let serialized = serde_json::to_string(&entries)
+        .map_err(|e| format!("Unable to serialize checkpoint history: {e:?}"))?;
+    caching::global().set(&get_history_key(), serialized).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn oldest_watermark_skips_empty_watermarks() {
+        let entries = vec![
+            CheckpointEntry::new("sync", "200".to_owned(), "300".to_owned()),
+            CheckpointEntry::new("sync", "".to_owned(), "200".to_owned()),
+        ];
+        assert_eq!(oldest_watermark(&entries, "300"), Some(200));
+        assert_eq!(oldest_watermark(&entries, "100"), Some(100));
+        assert_eq!(oldest_watermark(&[], ""), None);
+    }
+}
@@ -0,0 +1,103 @@
+//! # Caching Module
+//!
+//! Handles saving and reading the sync checkpoint (the last seen timestamp).
+//!
+//! ## Path
+//!
+//! caching/mod.rs
+//!
+//! # Description
+//!
+//! Defines the `CheckpointStore` trait the scraper uses for keeping the
+//! watermark under the `REDIS_KEY_NAME` key between runs and restarts. The
+//! backend is selected via the `LS_CHECKPOINT_BACKEND` configuration setting:
+//!
+//! - `redis`: (default) kept in Redis and shared by every instance
+//! - `file`: kept in a local JSON file written atomically
+//! - `sqlite`: kept in a table of the SQLite database (see `LS_SQLITE_PATH`)
+//!
+//! Single-node deployments can use the `file` or `sqlite` backends to run
+//! without Redis. The distributed sync lock (`acquire_lock`) is only
+//! available with Redis.
+//!
+//! ## Notes
+//!
+//! Defines a `CHECKPOINTS` instance to be set only once on app start (see `init`).
+
+use async_trait::async_trait;
+use once_cell::sync::OnceCell;
+use std::fmt::Debug;
+use std::sync::Arc;
+use tracing::{event, Level};
+
+use crate::env_config::{EnvConfig, LS_CHECKPOINT_BACKEND, REDIS_KEY_NAME};
+
+mod file_store;
+pub mod history;
+mod redis_store;
+mod sqlite_store;
+
+pub use file_store::FileCheckpointStore;
+pub use redis_store::{acquire_lock, RedisCheckpointStore, RedisPool};
+pub use sqlite_store::SqliteCheckpointStore;
+
+/// The cell to init and hold the checkpoint backend instance (only writable once).
+static CHECKPOINTS: OnceCell<Box<dyn CheckpointStore>> = OnceCell::new();
+
+/// Operations every checkpoint backend must provide.
+#[async_trait]
+pub trait CheckpointStore: Send + Sync + Debug {
+    /// Reads the value stored under the key (empty if none was stored yet).
+    async fn get(&self, key: &str) -> Result<String, String>;
+
+    /// Stores the value under the key.
+    async fn set(&self, key: &str, val: String) -> Result<(), String>;
+}
+
+/// Selects and sets up the checkpoint backend configured via `LS_CHECKPOINT_BACKEND`.
+/// The `redis` backend uses the given shared connection.
+pub async fn init(redis: Arc<RedisPool>) -> tokio::io::Result<()> {
+    let backend = EnvConfig::global().get_val(LS_CHECKPOINT_BACKEND);
+    let store: Box<dyn CheckpointStore> = match backend.as_str() {
+        "redis" => Box::new(RedisCheckpointStore::new(redis)),
+        "file" => Box::new(FileCheckpointStore::new()),
+        "sqlite" => Box::new(SqliteCheckpointStore::new()?),
+        other => {
+            return Err(tokio::io::Error::new(
+                tokio::io::ErrorKind::InvalidInput,
+                format!("Unknown checkpoint backend '{other}'"),
+            ))
+        }
+    };
+
+    event!(Level::INFO, "Using {backend} checkpoint backend");
+    if CHECKPOINTS.set(store).is_err() {
+        event!(Level::WARN, "Checkpoint backend was already initialized");
+    }
+    Ok(())
+}
+
+/// Returns the checkpoint backend set up via `init`.
+pub fn global() -> &'static dyn CheckpointStore {
+    CHECKPOINTS
+        .get()
+        .expect("Checkpoint backend is not initialized")
+        .as_ref()
+}
+
+/// Determines whether the checkpoint backend is shared between instances
+/// through Redis, which is required for the distributed sync lock.
+pub fn uses_redis() -> bool {
+    EnvConfig::global().get_val(LS_CHECKPOINT_BACKEND) == "redis"
+}
+
+pub async fn get_cached_val() -> Result<String, String> {
+    let key_name = EnvConfig::global().get_val(REDIS_KEY_NAME);
+    global().get(&key_name).await
+}
+
+pub async fn set_cached_val(val: String) -> Result<(), String> {
+    let key_name = EnvConfig::global().get_val(REDIS_KEY_NAME);
+    global().set(&key_name, val).await?;
+    Ok(())
+}
@@ -0,0 +1,378 @@
+//! # Redis Checkpoint Store Module
+//!
+//! Handles connection to redis and chaching values.
+//!
+//! ## Path
+//!
+//! caching/redis_store.rs
+//!
+//! # Description
+//!
+//! Allows caching a value via Redis using the REDIS_CACH_KEY.
+//!
+//! Connections are shared through a `RedisPool` created once on app start
+//! and held in `LogScraperState`. It wraps an async `ConnectionManager`
+//! which is connected on first use and then reconnects by itself (with
+//! `LS_REDIS_RETRIES` attempts), so commands never block the runtime and
+//! no connection is opened per call. Connecting and every command time out
+//! after `LS_REDIS_CONNECT_TIMEOUT` and `LS_REDIS_RESPONSE_TIMEOUT` seconds.
+//!
+//! `REDIS_URL` takes one or more comma separated nodes, either as
+//! `host:port` or as `redis://` / `rediss://` (TLS) urls. `REDIS_USERNAME`
+//! and `REDIS_PASSWORD` are applied to every node when set (to the master
+//! only with sentinels, which take credentials in their urls). Depending on
+//! `LS_REDIS_MODE` the nodes are:
+//!
+//! - `standalone`: (default) the Redis server (only the first node is used)
+//! - `sentinel`: the sentinels to discover the `LS_REDIS_SENTINEL_MASTER`
+//!   master from, which is discovered again after connection errors (i.e.
+//!   after a failover)
+//! - `cluster`: the initial cluster nodes
+//!
+//! Also provides a distributed lock (`acquire_lock`) so only one instance
+//! works on a given source at a time. The lock is a key set with `NX` and
+//! an expiry (`LS_LOCK_TTL` seconds) holding a token unique to the holder,
+//! which is checked again when releasing so an expired lock taken over by
+//! another instance is never released by the previous holder.
+
+use async_trait::async_trait;
+use redis::aio::{ConnectionManager, ConnectionManagerConfig};
+use redis::cluster::{ClusterClient, ClusterClientBuilder};
+use redis::cluster_async::ClusterConnection;
+use redis::sentinel::{Sentinel, SentinelNodeConnectionInfo};
+use redis::{
+    Cmd, ConnectionAddr, ConnectionInfo, FromRedisValue, IntoConnectionInfo, RedisConnectionInfo,
+    RedisResult, TlsMode,
+};
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::Mutex;
+use tracing::{instrument, trace, warn};
+
+use crate::caching::CheckpointStore;
+use crate::env_config::{
+    EnvConfig, LS_LOCK_TTL, LS_REDIS_CONNECT_TIMEOUT, LS_REDIS_MODE, LS_REDIS_RESPONSE_TIMEOUT,
+    LS_REDIS_RETRIES, LS_REDIS_SENTINEL_MASTER, REDIS_KEY_NAME, REDIS_PASSWORD, REDIS_URL,
+    REDIS_USERNAME,
+};
+
+/// Deletes the lock key only while it still holds the given token.
+const RELEASE_LOCK_SCRIPT: &str = r#"
+if redis.call("GET", KEYS[1]) == ARGV[1] then
+    return redis.call("DEL", KEYS[1])
+else
+    return 0
+end
+"#;
+
+/// The longest wait between two reconnect attempts in milliseconds.
+const RECONNECT_MAX_DELAY_MS: u64 = 2000;
+
+/// A distributed lock held in Redis until released (or until it expires).
+#[derive(Debug)]
+pub struct RedisLock {
+    key: String,
+    token: String,
+}
+
+/// Where to connect to, depending on `LS_REDIS_MODE`.
+enum RedisTarget {
+    Standalone(redis::Client),
+    Sentinel {
+        sentinels: Vec<ConnectionInfo>,
+        master: String,
+        master_info: SentinelNodeConnectionInfo,
+    },
+    Cluster(ClusterClient),
+}
+
+/// An established connection, cheap to clone and multiplexed.
+#[derive(Clone)]
+enum RedisConnection {
+    Single(Box<ConnectionManager>),
+    Cluster(ClusterConnection),
+}
+
+/// Shared async connection to Redis, connected lazily on first use.
+pub struct RedisPool {
+    mode: String,
+    target: RedisTarget,
+    config: ConnectionManagerConfig,
+    connection: Mutex<Option<RedisConnection>>,
+}
+
+impl fmt::Debug for RedisPool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RedisPool")
+            .field("mode", &self.mode)
+            .finish()
+    }
+}
+
+/// Formats a redis error using its detail when there is one.
+fn describe_error(context: &str, err: redis::RedisError) -> String {
+    match err.detail() {
+        Some(d) => format!("{context}: {d}"),
+        None => format!("{context}: {err:?}"),
+    }
+}
+
+/// Returns the configured `REDIS_USERNAME` and `REDIS_PASSWORD` (if set).
+fn get_credentials() -> (Option<String>, Option<String>) {
+    let env = EnvConfig::global();
+    let non_empty = |key: &str| Some(env.get_val(key)).filter(|val| !val.is_empty());
+    (non_empty(REDIS_USERNAME), non_empty(REDIS_PASSWORD))
+}
+
+/// Parses the comma separated `REDIS_URL` nodes, defaulting to the `redis://`
+/// scheme. The configured credentials are applied when `authenticate` is set
+/// (sentinels use the ones in their urls instead).
+fn get_nodes(authenticate: bool) -> Result<Vec<ConnectionInfo>, String> {
+    let env = EnvConfig::global();
+    let (username, password) = get_credentials();
+
+    env.get_val(REDIS_URL)
+        .split(',')
+        .map(str::trim)
+        .filter(|node| !node.is_empty())
+        .map(|node| {
+            let url = if node.contains("://") {
+                node.to_owned()
+            } else {
+                format!("redis://{node}")
+            };
+            let mut info = url
+                .as_str()
+                .into_connection_info()
+                .map_err(|e| describe_error("Invalid REDIS_URL", e))?;
+            if authenticate && username.is_some() {
+                info.redis.username.clone_from(&username);
+            }
+            if authenticate && password.is_some() {
+                info.redis.password.clone_from(&password);
+            }
+            Ok(info)
+        })
+        .collect()
+}
+
+impl RedisPool {
+    /// Creates a new `RedisPool` struct from the configured nodes, mode and timeouts.
+    /// Doesn't connect yet, so the app starts even when Redis is unreachable.
+    pub fn new() -> Result<RedisPool, String> {
+        let env = EnvConfig::global();
+        let mode = env.get_val(LS_REDIS_MODE);
+        let nodes = get_nodes(mode != "sentinel")?;
+        let first = nodes
+            .first()
+            .cloned()
+            .ok_or_else(|| "REDIS_URL doesn't contain any node".to_owned())?;
+
+        let seconds = |key: &str| Duration::from_secs(env.get_val(key).parse::<u64>().unwrap_or(5));
+        let retries = env.get_val(LS_REDIS_RETRIES).parse::<usize>().unwrap_or(2);
+        // the factor multiplies the delay between reconnect attempts (starting
+        // at 1s), the default of 100 would stall commands for minutes
+        let config = ConnectionManagerConfig::new()
+            .set_factor(2)
+            .set_max_delay(RECONNECT_MAX_DELAY_MS)
+            .set_connection_timeout(seconds(LS_REDIS_CONNECT_TIMEOUT))
+            .set_response_timeout(seconds(LS_REDIS_RESPONSE_TIMEOUT))
+            .set_number_of_retries(retries);
+
+        let target = match mode.as_str() {
+            "standalone" => RedisTarget::Standalone(
+                redis::Client::open(first)
+                    .map_err(|e| describe_error("Error Obtaining Redis Client", e))?,
+            ),
+            "sentinel" => {
+                let (username, password) = get_credentials();
+                // the master uses TLS when the sentinels do
+                let tls_mode = match first.addr {
+                    ConnectionAddr::TcpTls { insecure: true, .. } => Some(TlsMode::Insecure),
+                    ConnectionAddr::TcpTls { .. } => Some(TlsMode::Secure),
+                    _ => None,
+                };
+                RedisTarget::Sentinel {
+                    sentinels: nodes,
+                    master: env.get_val(LS_REDIS_SENTINEL_MASTER),
+                    master_info: SentinelNodeConnectionInfo {
+                        tls_mode,
+                        redis_connection_info: Some(RedisConnectionInfo {
+                            username,
+                            password,
+                            ..Default::default()
+                        }),
+                    },
+                }
+            }
+            "cluster" => RedisTarget::Cluster(
+                ClusterClientBuilder::new(nodes)
+                    .connection_timeout(seconds(LS_REDIS_CONNECT_TIMEOUT))
+                    .response_timeout(seconds(LS_REDIS_RESPONSE_TIMEOUT))
+                    .retries(retries as u32)
+                    .build()
+                    .map_err(|e| describe_error("Error Obtaining Redis Cluster Client", e))?,
+            ),
+            other => return Err(format!("Unknown redis mode '{other}'")),
+        };
+
+        Ok(RedisPool {
+            mode,
+            target,
+            config,
+            connection: Mutex::new(None),
+        })
+    }
+
+    /// Returns a handle to the shared connection, connecting first if needed.
+    #[instrument(name = "establish_redis_connection", skip(self))]
+    async fn connection(&self) -> RedisResult<RedisConnection> {
+        let mut current = self.connection.lock().await;
+        if let Some(connection) = current.as_ref() {
+            return Ok(connection.clone());
+        }
+
+        trace!("Connecting to redis ({})", self.mode);
+        let connection = match &self.target {
+            RedisTarget::Standalone(client) => RedisConnection::Single(Box::new(
+                ConnectionManager::new_with_config(client.clone(), self.config.clone()).await?,
+            )),
+            RedisTarget::Sentinel {
+                sentinels,
+                master,
+                master_info,
+            } => {
+                let client = Sentinel::build(sentinels.clone())?
+                    .async_master_for(master, Some(master_info))
+                    .await?;
+                RedisConnection::Single(Box::new(
+                    ConnectionManager::new_with_config(client, self.config.clone()).await?,
+                ))
+            }
+            RedisTarget::Cluster(client) => {
+                RedisConnection::Cluster(client.get_async_connection().await?)
+            }
+        };
+        *current = Some(connection.clone());
+        Ok(connection)
+    }
+
+    /// Runs the command on the shared connection. With sentinels, the master
+    /// is discovered again on the next command after connection errors or
+    /// when the node was demoted to a replica.
+    pub async fn query<T: FromRedisValue>(&self, cmd: &Cmd) -> RedisResult<T> {
+        let result = match self.connection().await? {
+            RedisConnection::Single(mut connection) => cmd.query_async(&mut *connection).await,
+            RedisConnection::Cluster(mut connection) => cmd.query_async(&mut connection).await,
+        };
+
+        if let (Err(err), RedisTarget::Sentinel { .. }) = (&result, &self.target) {
+            if err.is_io_error()
+                || err.is_connection_dropped()
+                || err.kind() == redis::ErrorKind::ReadOnly
+            {
+                warn!("Discovering the redis master again: {err}");
+                *self.connection.lock().await = None;
+            }
+        }
+        result
+    }
+
+    /// Checks that Redis responds to a `PING`.
+    pub async fn ping(&self) -> Result<(), String> {
+        self.query::<String>(&redis::cmd("PING"))
+            .await
+            .map(|_| ())
+            .map_err(|e| describe_error("Redis did not respond", e))
+    }
+}
+
+/// Checkpoint backend that keeps values in Redis, shared by every instance.
+#[derive(Debug)]
+pub struct RedisCheckpointStore {
+    pool: Arc<RedisPool>,
+}
+
+impl RedisCheckpointStore {
+    /// Creates a new `RedisCheckpointStore` struct using the shared connection.
+    pub fn new(pool: Arc<RedisPool>) -> RedisCheckpointStore {
+        RedisCheckpointStore { pool }
+    }
+}
+
+#[async_trait]
+impl CheckpointStore for RedisCheckpointStore {
+    async fn get(&self, key: &str) -> Result<String, String> {
+        let result: redis::RedisResult<Option<String>> =
+            self.pool.query(redis::cmd("GET").arg(key)).await;
+        // only a missing key reads as empty, failures must not look like one
+        result
+            .map(|string_value| string_value.unwrap_or_default())
+            .map_err(|e| describe_error("Unable to read value", e))
+    }
+
+    async fn set(&self, key: &str, val: String) -> Result<(), String> {
+        self.pool
+            .query::<()>(redis::cmd("SET").arg(key).arg(val))
+            .await
+            .map_err(|e| describe_error("Unable to set value", e))
+    }
+}
+
+/// Attempts to take the distributed lock for the given source without
+/// waiting. Returns `None` when another instance holds it.
+#[instrument(name = "acquire_redis_lock", skip(pool))]
+pub async fn acquire_lock(pool: &RedisPool, source: &str) -> Result<Option<RedisLock>, String> {
+    let env = EnvConfig::global();
+    let ttl_seconds = env.get_val(LS_LOCK_TTL).parse::<u64>().unwrap_or(300);
+    let key = format!("{}:lock:{source}", env.get_val(REDIS_KEY_NAME));
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or(0);
+    let hostname = std::env::var("HOSTNAME").unwrap_or_default();
+    let token = format!("{hostname}-{}-{nanos}", std::process::id());
+
+    let acquired: Option<String> = pool
+        .query(
+            redis::cmd("SET")
+                .arg(&key)
+                .arg(&token)
+                .arg("NX")
+                .arg("EX")
+                .arg(ttl_seconds),
+        )
+        .await
+        .map_err(|e| format!("Unable to acquire lock {key}: {e:?}"))?;
+
+    match acquired {
+        Some(_) => {
+            trace!("Acquired lock {key}");
+            Ok(Some(RedisLock { key, token }))
+        }
+        None => Ok(None),
+    }
+}
+
+impl RedisLock {
+    /// Releases the lock if it's still held by this instance.
+    #[instrument(name = "release_redis_lock", skip(pool))]
+    pub async fn release(self, pool: &RedisPool) -> Result<(), String> {
+        let released: i32 = pool
+            .query(
+                redis::cmd("EVAL")
+                    .arg(RELEASE_LOCK_SCRIPT)
+                    .arg(1)
+                    .arg(&self.key)
+                    .arg(&self.token),
+            )
+            .await
+            .map_err(|e| format!("Unable to release lock {}: {e:?}", self.key))?;
+        if released == 0 {
+            return Err(format!("Lock {} expired before it was released", self.key));
+        }
+        trace!("Released lock {}", self.key);
+        Ok(())
+    }
+}
@@ -0,0 +1,106 @@
+//! # SQLite Checkpoint Store Module
+//!
+//! Handles saving and reading checkpoints to and from an embedded SQLite database.
+//!
+//! ## Path
+//!
+//! caching/sqlite_store.rs
+//!
+//! # Description
+//!
+//! Keeps every checkpoint as a row of the `checkpoints` table in the same
+//! database the `sqlite` storage backend uses (see `LS_SQLITE_PATH`), so the
+//! logs and the watermark are stored together. Queries run on tokio's
+//! blocking thread pool as they block while they run.
+
+use async_trait::async_trait;
+use rusqlite::{params, Connection, OptionalExtension};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use tracing::{event, Level};
+
+use crate::caching::CheckpointStore;
+use crate::storage::get_db_path;
+
+/// Statement for creating the table if it doesn't exist yet.
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS checkpoints (
+        key TEXT PRIMARY KEY,
+        value TEXT NOT NULL,
+        updated_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
+    );
+";
+
+/// Checkpoint backend that keeps values in a SQLite table.
+#[derive(Debug)]
+pub struct SqliteCheckpointStore {
+    connection: Arc<Mutex<Connection>>,
+}
+
+impl SqliteCheckpointStore {
+    /// Creates a new `SqliteCheckpointStore` struct, opening (or creating) the
+    /// database and ensuring the table exists.
+    pub fn new() -> tokio::io::Result<SqliteCheckpointStore> {
+        let db_path = get_db_path();
+        if let Some(parent) = Path::new(&db_path).parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        event!(Level::INFO, "Opening SQLite checkpoints at {db_path}");
+        let connection = Connection::open(&db_path).map_err(tokio::io::Error::other)?;
+        connection
+            .execute_batch(SCHEMA)
+            .map_err(tokio::io::Error::other)?;
+
+        Ok(SqliteCheckpointStore {
+            connection: Arc::new(Mutex::new(connection)),
+        })
+    }
+
+    /// Runs the query with the connection on the blocking thread pool.
+    async fn run<T, F>(&self, query: F) -> Result<T, String>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Connection) -> Result<T, String> + Send + 'static,
+    {
+        let connection = self.connection.clone();
+        tokio::task::spawn_blocking(move || {
+            let conn = connection.lock().map_err(|e| format!("{e:?}"))?;
+            query(&conn)
+        })
+        .await
+        .map_err(|e| format!("{e:?}"))?
+    }
+}
+
+#[async_trait]
+impl CheckpointStore for SqliteCheckpointStore {
+    async fn get(&self, key: &str) -> Result<String, String> {
+        let key = key.to_owned();
+        self.run(move |conn| {
+            conn.query_row(
+                "SELECT value FROM checkpoints WHERE key = ?1",
+                params![key],
+                |row| row.get::<_, String>(0),
+            )
+            .optional()
+            .map(|value| value.unwrap_or_default())
+            .map_err(|e| format!("Unable to read checkpoint {key}: {e:?}"))
+        })
+        .await
+    }
+
+    async fn set(&self, key: &str, val: String) -> Result<(), String> {
+        let key = key.to_owned();
+        self.run(move |conn| {
+            conn.execute(
+                "INSERT INTO checkpoints (key, value) VALUES (?1, ?2)
+                 ON CONFLICT(key) DO UPDATE SET value = excluded.value, updated_at = CURRENT_TIMESTAMP",
+                params![key, val],
+            )
+            .map(|_| ())
+            .map_err(|e| format!("Unable to write checkpoint {key}: {e:?}"))
+        })
+        .await
+    }
+}
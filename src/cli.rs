@@ -0,0 +1,398 @@
+//! # Cli Module
+//!
+//! Handles the command line interface of the binary.
+//!
+//! ## Path
+//!
+//! cli.rs
+//!
+//! # Description
+//!
+//! Without a command (or with `serve`) the HTTP server starts. Every other
+//! command runs once against the configured storage and checkpoint backends
+//! and exits, failing with a non-zero exit code, so they can run as
+//! Kubernetes CronJobs or locally. Their output is written to stdout while
+//! log messages go to stderr.
+//!
+//! ```bash
+//! # fetch new logs once, i.e. from a CronJob instead of the schedule
+//! log-scraper sync-once
+//!
+//! # fetch the logs since a timestamp again, syncing until caught up
+//! log-scraper backfill --from 2023-01-01T00:00:00Z
+//!
+//! # list, print and search stored logs
+//! log-scraper list --prefix app_2023-01
+//! log-scraper cat app_2023-01-01.log --tail 20
+//! log-scraper search 'level:error' --from 2023-01-01T00:00:00Z --limit 10
+//!
+//! # export stored logs (optionally limited to files and a time range) to parquet
+//! log-scraper export ./logs.parquet --file app_2023-01-01.log --from 2023-01-01T00:00:00Z
+//!
+//! # verify files (all files and deleted ones when none are given) against their hash chains
+//! log-scraper verify app_2023-01-01.log
+//!
+//! # rewrite files (all files when none are given) with the active encryption key
+//! log-scraper reencrypt
+//!
+//! # free up storage when over the limits, evicting the oldest files
+//! log-scraper gc --policy evict
+//! ```
+//!
+//! ## Notes
+//!
+//! One-off commands don't take part in the leader election. `sync-once`
+//! and `backfill` rely on the same locks as the scheduled syncs, so they
+//! never run at the same time as another instance's sync.
+//!
+//! The search index can only be opened by one process at a time, so search
+//! (and indexing synced logs) isn't available to commands while a server
+//! uses the same `LS_SEARCH_INDEX_PATH`. Use `GET /logs/search` instead.
+
+use actix_web::web::Data;
+use clap::{Parser, Subcommand};
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use tracing::{event, Level};
+
+use crate::api::api_types::parse_timestamp_param;
+use crate::caching::{self, RedisPool};
+use crate::scraper::{self, SyncReport};
+use crate::{storage, LogScraperState};
+
+/// Fetches, persists and manages logs sourced from New Relic.
+#[derive(Parser, Debug)]
+#[command(version, about)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+/// The commands of the binary.
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Starts the HTTP server and the scheduled jobs (the default).
+    Serve,
+    /// Fetches new logs once and prints what was synced.
+    SyncOnce,
+    /// Moves the watermark back and syncs until caught up.
+    Backfill {
+        /// Timestamp (milliseconds or RFC 3339) to fetch the logs since.
+        #[arg(long, value_parser = parse_timestamp)]
+        from: i64,
+    },
+    /// Lists the stored log files.
+    List {
+        /// Only lists the files starting with the prefix.
+        #[arg(long)]
+        prefix: Option<String>,
+        /// Prints the files with their metadata as JSON.
+        #[arg(long)]
+        json: bool,
+    },
+    /// Prints the lines of a log file (fetching it from the archive if needed).
+    Cat {
+        filename: String,
+        /// Only prints the last lines.
+        #[arg(long)]
+        tail: Option<usize>,
+    },
+    /// Searches the stored logs via the full-text search index.
+    Search {
+        query: String,
+        /// Timestamp (milliseconds or RFC 3339) of the oldest matches.
+        #[arg(long, value_parser = parse_timestamp)]
+        from: Option<i64>,
+        /// Timestamp (milliseconds or RFC 3339) of the newest matches.
+        #[arg(long, value_parser = parse_timestamp)]
+        to: Option<i64>,
+        #[arg(long, default_value_t = 100)]
+        limit: usize,
+        /// Prints the matches with their highlights as JSON.
+        #[arg(long)]
+        json: bool,
+    },
+    /// Exports stored logs to a parquet file.
+    Export {
+        output: String,
+        /// Only exports the given files (all files when none are given).
+        #[arg(long = "file")]
+        files: Vec<String>,
+        #[arg(long, value_parser = parse_timestamp)]
+        from: Option<i64>,
+        #[arg(long, value_parser = parse_timestamp)]
+        to: Option<i64>,
+    },
+    /// Verifies files (all files and deleted ones when none are given) against their hash chains.
+    Verify { files: Vec<String> },
+    /// Rewrites files (all files when none are given) with the active encryption key.
+    Reencrypt { files: Vec<String> },
+    /// Frees up storage when over the limits (see `LS_QUOTA_BYTES` and `LS_MIN_FREE_BYTES`).
+    Gc {
+        /// Policy to apply instead of `LS_QUOTA_POLICY`.
+        #[arg(long, value_parser = ["evict", "compress"])]
+        policy: Option<String>,
+    },
+}
+
+/// Parses a timestamp argument given in milliseconds or RFC 3339.
+fn parse_timestamp(value: &str) -> Result<i64, String> {
+    parse_timestamp_param(value).ok_or(format!(
+        "Invalid timestamp '{value}', expected milliseconds or RFC 3339"
+    ))
+}
+
+/// Prints the value as pretty JSON to stdout.
+fn print_json<T: serde::Serialize>(value: &T) -> Result<(), String> {
+    let json = serde_json::to_string_pretty(value)
+        .map_err(|e| format!("Unable to serialize output: {e:?}"))?;
+    println!("{json}");
+    Ok(())
+}
+
+/// Runs a one-off command.
+pub async fn run(command: Command, redis: Arc<RedisPool>) -> Result<(), String> {
+    let data = || {
+        Data::new(LogScraperState {
+            last_seen: Mutex::new("".to_owned()),
+            redis: redis.clone(),
+        })
+    };
+    match command {
+        Command::Serve => Err("The serve command starts the server".to_owned()),
+        Command::SyncOnce => sync_once(data()).await,
+        Command::Backfill { from } => backfill(data(), from).await,
+        Command::List { prefix, json } => list(prefix, json).await,
+        Command::Cat { filename, tail } => cat(&filename, tail).await,
+        Command::Search {
+            query,
+            from,
+            to,
+            limit,
+            json,
+        } => search(&query, from, to, limit, json).await,
+        Command::Export {
+            output,
+            files,
+            from,
+            to,
+        } => export(&output, &files, from, to).await,
+        Command::Verify { files } => verify(files).await,
+        Command::Reencrypt { files } => reencrypt(files).await,
+        Command::Gc { policy } => gc(policy.as_deref()).await,
+    }
+}
+
+/// Returns the given files or all files held by the storage backend.
+async fn files_or_all(files: Vec<String>) -> Vec<String> {
+    if files.is_empty() {
+        storage::global().get_log_filenames().await
+    } else {
+        files
+    }
+}
+
+/// Fails with the reason a sync didn't store any (or all) logs.
+fn check_sync(report: &SyncReport) -> Result<(), String> {
+    match (&report.error, report.skipped) {
+        (Some(err), _) => Err(format!("Sync failed: {err}")),
+        (None, true) => Err("Skipped, another instance is syncing".to_owned()),
+        (None, false) => Ok(()),
+    }
+}
+
+/// Fetches new logs once, printing the sync report.
+async fn sync_once(data: Data<LogScraperState>) -> Result<(), String> {
+    let report = scraper::run_sync(data).await;
+    print_json(&report)?;
+    match report.skipped {
+        // another instance syncing isn't a failure of a one-off sync
+        true => Ok(()),
+        false => check_sync(&report),
+    }
+}
+
+/// Moves the watermark back to the timestamp and syncs until a sync
+/// fetches less than a full batch.
+async fn backfill(data: Data<LogScraperState>, from: i64) -> Result<(), String> {
+    let current = caching::get_cached_val().await.unwrap_or_default();
+    if current.parse::<i64>().is_ok_and(|current| from >= current) {
+        return Err(format!(
+            "Can only backfill from a timestamp before the current watermark ({current})"
+        ));
+    }
+    scraper::set_watermark(data.clone(), from.to_string(), "backfill").await?;
+
+    let mut total = 0;
+    loop {
+        let report = scraper::run_sync(data.clone()).await;
+        check_sync(&report)?;
+        total += report.logs_fetched;
+        event!(
+            Level::INFO,
+            "Backfilled {} logs up to {} ({total} in total)",
+            report.logs_fetched,
+            report.watermark_after
+        );
+        if !report.hit_limit || report.watermark_after == report.watermark_before {
+            break;
+        }
+    }
+    println!("Backfilled {total} logs since {from}");
+    Ok(())
+}
+
+/// Lists the stored files, including archived ones no longer on disk.
+async fn list(prefix: Option<String>, json: bool) -> Result<(), String> {
+    let mut files = storage::get_file_infos().await;
+    files.retain(|f| prefix.as_deref().is_none_or(|p| f.name.starts_with(p)));
+    files.sort_by(|a, b| a.name.cmp(&b.name));
+
+    if json {
+        return print_json(&files);
+    }
+    for file in files {
+        let lines = file.lines.map_or("-".to_owned(), |l| l.to_string());
+        let location = match (file.local, file.archived) {
+            (true, true) => "local, archived",
+            (true, false) => "local",
+            (false, _) => "archived",
+        };
+        println!(
+            "{}\t{} bytes\t{lines} lines\t{location}",
+            file.name, file.size
+        );
+    }
+    Ok(())
+}
+
+/// Prints the lines of a file, fetching it from the archive when it was
+/// removed from disk.
+async fn cat(filename: &str, tail: Option<usize>) -> Result<(), String> {
+    let filename =
+        storage::sanitize_filename(filename).ok_or(format!("Invalid filename '{filename}'"))?;
+    let store = storage::global();
+    if !store.has_file(&filename).await && !storage::archive::restore(&filename).await? {
+        return Err(format!("Unable to find file {filename}"));
+    }
+
+    let lines = match tail {
+        Some(count) => store
+            .get_last_lines(&filename, 0, count)
+            .await
+            .map(|mut lines| {
+                lines.reverse();
+                lines
+            }),
+        None => store.get_all_lines(&filename).await,
+    }
+    .map_err(|e| format!("Unable to read {filename}: {e:?}"))?;
+    for line in lines {
+        println!("{}", line.trim_end());
+    }
+    Ok(())
+}
+
+/// Prints the matches of a full-text search.
+async fn search(
+    query: &str,
+    from: Option<i64>,
+    to: Option<i64>,
+    limit: usize,
+    json: bool,
+) -> Result<(), String> {
+    let query = query.to_owned();
+    let (total, hits) =
+        storage::search_index::run(move |index| index.search(&query, from, to, limit))
+            .await
+            .ok_or("Search is not enabled")??;
+
+    if json {
+        return print_json(&hits);
+    }
+    for hit in &hits {
+        println!("{}:{}\t{}", hit.filename, hit.line, hit.content.trim_end());
+    }
+    event!(Level::INFO, "Showing {} of {total} matches", hits.len());
+    Ok(())
+}
+
+/// Exports stored logs to a parquet file.
+async fn export(
+    output: &str,
+    files: &[String],
+    from: Option<i64>,
+    to: Option<i64>,
+) -> Result<(), String> {
+    let bytes = storage::parquet_export::export_parquet(files, from, to).await?;
+    tokio::fs::write(output, &bytes)
+        .await
+        .map_err(|e| format!("Unable to write {output}: {e:?}"))?;
+    event!(Level::INFO, "Exported {} bytes to {output}", bytes.len());
+    Ok(())
+}
+
+/// Verifies files against their hash chains, failing if any doesn't pass.
+/// Without files, deleted files are checked against their tombstones too.
+async fn verify(files: Vec<String>) -> Result<(), String> {
+    let mut files = match files.is_empty() {
+        true => [
+            files_or_all(files).await,
+            storage::hash_chain::deleted_files().await,
+        ]
+        .concat(),
+        false => files,
+    };
+    files.sort();
+    files.dedup();
+
+    let mut failures = 0;
+    for filename in files {
+        let report = storage::hash_chain::verify(&filename).await;
+        if let (true, Some(deleted_at)) = (report.ok, report.deleted_at) {
+            let deleted_at = deleted_at.to_rfc3339();
+            println!(
+                "{filename}: deleted at {deleted_at} ({} batches, {} lines)",
+                report.batches, report.lines
+            );
+        } else if report.ok {
+            println!(
+                "{filename}: ok ({} batches, {} lines)",
+                report.batches, report.lines
+            );
+        } else {
+            failures += 1;
+            println!("{filename}: FAILED {:?}", report.errors);
+        }
+    }
+
+    if failures > 0 {
+        return Err(format!("{failures} file(s) failed verification"));
+    }
+    Ok(())
+}
+
+/// Rewrites files with the active encryption key after a key rotation.
+async fn reencrypt(files: Vec<String>) -> Result<(), String> {
+    if !storage::encryption::is_enabled() {
+        return Err("Encryption is not enabled".to_owned());
+    }
+    let store = storage::global();
+    for filename in files_or_all(files).await {
+        let _lock = storage::file_lock::acquire(&filename)
+            .await
+            .map_err(|e| format!("Unable to lock {filename}: {e:?}"))?;
+        let rewritten = store
+            .reencrypt(&filename)
+            .await
+            .map_err(|e| format!("Unable to re-encrypt {filename}: {e:?}"))?;
+        event!(Level::INFO, "{filename}: re-encrypted {rewritten} lines");
+    }
+    Ok(())
+}
+
+/// Frees up storage when over the limits, printing the usage afterwards.
+async fn gc(policy: Option<&str>) -> Result<(), String> {
+    let status = storage::quota::collect(policy).await?;
+    print_json(&status)
+}
@@ -0,0 +1,114 @@
+//! # Adaptive Polling Module
+//!
+//! Decides when to poll for new logs based on the size of the last batch.
+//!
+//! ## Path
+//!
+//! cron_tasks/adaptive.rs
+//!
+//! # Description
+//!
+//! With `LS_ADAPTIVE_POLLING` enabled the `sync` job is no longer bound to
+//! `LS_POLL_SCHEDULE` after its first run. Instead the wait until the next
+//! poll is adjusted after every sync, staying between `LS_POLL_MIN_INTERVAL`
+//! and `LS_POLL_MAX_INTERVAL`:
+//!
+//! - a batch near the fetch limit (`NRLS_FETCH_LIMIT`) polls again after the
+//!   minimum interval, until caught up
+//! - a smaller batch halves the interval
+//! - an empty batch (or a failed sync) doubles the interval
+//! - a skipped sync keeps the interval
+//!
+//! ## Notes
+//!
+//! Pausing, resuming or rescheduling the job falls back to its cron schedule
+//! until the next sync.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use crate::env_config::{
+    EnvConfig, LS_ADAPTIVE_POLLING, LS_POLL_MAX_INTERVAL, LS_POLL_MIN_INTERVAL,
+};
+use crate::new_relic;
+use crate::scraper::SyncReport;
+
+/// The current interval between polls, in milliseconds.
+static INTERVAL_MS: AtomicU64 = AtomicU64::new(0);
+
+/// Returns whether the polling interval adapts to the size of the batches.
+pub fn enabled() -> bool {
+    EnvConfig::global().get_val(LS_ADAPTIVE_POLLING) == "true"
+}
+
+/// Returns the minimum and maximum interval between polls.
+fn get_bounds() -> (Duration, Duration) {
+    let env = EnvConfig::global();
+    let min = env
+        .get_val(LS_POLL_MIN_INTERVAL)
+        .parse::<u64>()
+        .unwrap_or(10)
+        .max(1);
+    let max = env
+        .get_val(LS_POLL_MAX_INTERVAL)
+        .parse::<u64>()
+        .unwrap_or(300)
+        .max(min);
+    (Duration::from_secs(min), Duration::from_secs(max))
+}
+
+/// Returns how long to wait before the next poll given the last sync.
+pub fn next_delay(report: &SyncReport) -> Duration {
+    let (min, max) = get_bounds();
+    let current = Duration::from_millis(INTERVAL_MS.load(Ordering::SeqCst)).clamp(min, max);
+    // i.e. 90 or more logs with the default limit of 100
+    let near_limit = report.hit_limit || report.logs_fetched * 10 >= new_relic::fetch_limit() * 9;
+
+    let next = match (report.skipped, report.error.is_some(), report.logs_fetched) {
+        (true, _, _) => current,
+        (false, false, _) if near_limit => min,
+        (false, false, fetched) if fetched > 0 => (current / 2).max(min),
+        _ => (current * 2).min(max),
+    };
+    INTERVAL_MS.store(next.as_millis() as u64, Ordering::SeqCst);
+    next
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::env_config::init_test_config;
+
+    fn report(logs_fetched: usize, skipped: bool, error: Option<&str>) -> SyncReport {
+        SyncReport {
+            skipped,
+            logs_fetched,
+            error: error.map(str::to_owned),
+            ..Default::default()
+        }
+    }
+
+    // the interval is kept across syncs, so the steps run in order within one test
+    #[test]
+    fn next_delay_adapts_to_the_batch_size() {
+        init_test_config();
+        let secs = |report: SyncReport| next_delay(&report).as_secs();
+
+        // empty batches and failed syncs double the interval (from the minimum of 10s)
+        assert_eq!(secs(report(0, false, None)), 20);
+        assert_eq!(secs(report(0, false, Some("timeout"))), 40);
+        // skipped syncs keep it
+        assert_eq!(secs(report(0, true, None)), 40);
+        // smaller batches halve it
+        assert_eq!(secs(report(10, false, None)), 20);
+        // batches near the fetch limit (100) poll again after the minimum
+        assert_eq!(secs(report(95, false, None)), 10);
+        assert_eq!(secs(report(60, false, None)), 10);
+
+        // the interval never exceeds the maximum of 300s
+        let delays = (0..8)
+            .map(|_| secs(report(0, false, None)))
+            .collect::<Vec<u64>>();
+        assert_eq!(delays, [20, 40, 80, 160, 300, 300, 300, 300]);
+    }
+}
@@ -0,0 +1,124 @@
+//! # Cron Tasks Module
+//!
+//! Handles starting and executing tasks via cron schedules.
+//!
+//! ## Path
+//!
+//! cron_tasks/mod.rs
+//!
+//! # Description
+//!
+//! This module registers the background tasks and functions as named jobs
+//! on the `scheduler`:
+//!
+//! - `sync`: fetches new logs on the `LS_POLL_SCHEDULE`
+//! - `archive`: archives closed log files on the `LS_ARCHIVE_SCHEDULE` (when enabled)
+//!
+//! Scheduled runs are skipped on followers (see the `leader` module). Jobs
+//! can be paused, rescheduled and triggered at runtime via the scheduler and
+//! every run is recorded in `runs`. A reload of the config changing
+//! `LS_POLL_SCHEDULE` or `LS_ARCHIVE_SCHEDULE` moves the job to its new
+//! schedule (see `apply_config`).
+
+use actix_web::web::Data;
+use std::sync::Arc;
+use tracing::{event, Level};
+
+pub mod adaptive;
+pub mod runs;
+pub mod scheduler;
+
+use crate::{
+    env_config::{
+        EnvConfig, ReloadReport, LS_ARCHIVE_ENABLED, LS_ARCHIVE_SCHEDULE, LS_POLL_SCHEDULE,
+    },
+    scraper, storage, LogScraperState,
+};
+use runs::{JobOutcome, JobStatus};
+use scheduler::{JobFn, Scheduler};
+
+/// Name of the job synchronizing logs with the remote server.
+pub const SYNC_JOB: &str = "sync";
+
+/// Name of the job archiving closed log files to S3.
+pub const ARCHIVE_JOB: &str = "archive";
+
+/// Starts up all the cron tasks and schedules for this module.
+/// Fails when a configured schedule isn't a valid cron expression.
+pub fn start(app_state: Data<LogScraperState>) -> Result<Scheduler, String> {
+    let env = EnvConfig::global();
+    let mut scheduler = Scheduler::new();
+
+    // every 5 minutes, starting at 1 minute past the hour (by default)
+    scheduler.add(
+        SYNC_JOB,
+        &env.get_val(LS_POLL_SCHEDULE),
+        log_sync_job(app_state),
+    )?;
+
+    if env.get_val(LS_ARCHIVE_ENABLED) == "true" {
+        scheduler.add(
+            ARCHIVE_JOB,
+            &env.get_val(LS_ARCHIVE_SCHEDULE),
+            archive_job(),
+        )?;
+    }
+    Ok(scheduler)
+}
+
+/// Moves the jobs to the schedules changed by a reload of the config.
+pub fn apply_config(scheduler: &Scheduler, report: &ReloadReport) {
+    let env = EnvConfig::global();
+    for (name, key) in [
+        (SYNC_JOB, LS_POLL_SCHEDULE),
+        (ARCHIVE_JOB, LS_ARCHIVE_SCHEDULE),
+    ] {
+        if !report.changed.iter().any(|changed| changed == key) {
+            continue;
+        }
+        if let Some(job) = scheduler.get(name) {
+            if let Err(err) = job.reschedule(&env.get_val(key)) {
+                event!(Level::ERROR, "Unable to reschedule job {name}: {err}");
+            }
+        }
+    }
+}
+
+/// Runs a sync, reporting what was fetched and written.
+async fn sync_outcome(data: Data<LogScraperState>) -> JobOutcome {
+    let report = scraper::run_sync(data).await;
+    let (status, message) = match (&report.error, report.skipped) {
+        (Some(err), _) => (JobStatus::Failed, format!("Sync failed: {err}")),
+        (None, true) => (
+            JobStatus::Skipped,
+            "Skipped, another instance is syncing".to_owned(),
+        ),
+        (None, false) => (JobStatus::Succeeded, "Sync Complete!".to_owned()),
+    };
+    JobOutcome {
+        status,
+        message,
+        next_delay: adaptive::enabled().then(|| adaptive::next_delay(&report)),
+        sync: Some(report),
+    }
+}
+
+/// Creates the job for synchronizing logs with the remote server.
+fn log_sync_job(app_state: Data<LogScraperState>) -> JobFn {
+    Arc::new(move || Box::pin(sync_outcome(app_state.clone())))
+}
+
+/// Creates the job for archiving closed log files to S3.
+fn archive_job() -> JobFn {
+    Arc::new(|| {
+        Box::pin(async {
+            match storage::archive::run_archive().await {
+                Ok(_) => JobOutcome::new(JobStatus::Succeeded, "Archive Complete!"),
+                Err(err) => JobOutcome::new(
+                    JobStatus::Failed,
+                    &format!("An error occurred while archiving log files: {err}"),
+                ),
+            }
+        })
+    })
+}
@@ -0,0 +1,151 @@
+//! # Job Runs Module
+//!
+//! Records the runs of the scheduled jobs.
+//!
+//! ## Path
+//!
+//! cron_tasks/runs.rs
+//!
+//! # Description
+//!
+//! Every run of a job, whether started by its schedule or via the API, is
+//! recorded with its start and end, status and (for syncs) what was fetched
+//! and written. The most recent `LS_JOB_HISTORY` runs are kept in memory,
+//! so the history starts over when the service restarts.
+
+use chrono::{DateTime, Utc};
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+use tracing::{event, Level};
+
+use crate::env_config::{EnvConfig, LS_JOB_HISTORY};
+use crate::scraper::SyncReport;
+
+/// Trigger of runs started by the job's schedule.
+pub const TRIGGER_SCHEDULE: &str = "schedule";
+
+/// Trigger of runs started via the API.
+pub const TRIGGER_API: &str = "api";
+
+/// Most recent runs, newest first.
+static RUNS: Lazy<Mutex<VecDeque<JobRun>>> = Lazy::new(|| Mutex::new(VecDeque::new()));
+
+/// Id of the next run.
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// State of a job run.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum JobStatus {
+    Running,
+    Succeeded,
+    Failed,
+    Skipped,
+}
+
+/// What a job run did.
+#[derive(Debug, Clone)]
+pub struct JobOutcome {
+    pub status: JobStatus,
+    pub message: String,
+    pub sync: Option<SyncReport>,
+    /// When set, the job runs again after this delay instead of on its schedule.
+    pub next_delay: Option<Duration>,
+}
+
+impl JobOutcome {
+    /// Creates a new `JobOutcome` struct of a run that didn't sync.
+    pub fn new(status: JobStatus, message: &str) -> JobOutcome {
+        JobOutcome {
+            status,
+            message: message.to_owned(),
+            sync: None,
+            next_delay: None,
+        }
+    }
+}
+
+/// A recorded run of a job.
+#[derive(Serialize, Debug, Clone)]
+pub struct JobRun {
+    pub id: u64,
+    pub job: String,
+    /// What started the run: `schedule` or `api`.
+    pub trigger: String,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+    pub status: JobStatus,
+    pub message: String,
+    /// What the sync fetched and wrote (for `sync` runs).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sync: Option<SyncReport>,
+}
+
+fn get_max_runs() -> usize {
+    EnvConfig::global()
+        .get_val(LS_JOB_HISTORY)
+        .parse::<usize>()
+        .unwrap_or(100)
+}
+
+/// Adds or replaces the run, dropping the oldest ones past `LS_JOB_HISTORY`.
+fn store(run: &JobRun) {
+    let max_runs = get_max_runs();
+    let mut runs = RUNS.lock().unwrap_or_else(|e| e.into_inner());
+    match runs.iter_mut().find(|r| r.id == run.id) {
+        Some(existing) => *existing = run.clone(),
+        None => runs.push_front(run.clone()),
+    }
+    runs.truncate(max_runs);
+}
+
+/// Records the start of a run of the job.
+pub fn start(job: &str, trigger: &str) -> JobRun {
+    let record = JobRun {
+        id: NEXT_ID.fetch_add(1, Ordering::SeqCst),
+        job: job.to_owned(),
+        trigger: trigger.to_owned(),
+        started_at: Utc::now(),
+        finished_at: None,
+        status: JobStatus::Running,
+        message: String::new(),
+        sync: None,
+    };
+    store(&record);
+    record
+}
+
+/// Records how the run finished.
+pub fn finish(record: &mut JobRun, outcome: JobOutcome) {
+    record.finished_at = Some(Utc::now());
+    record.status = outcome.status;
+    record.message = outcome.message;
+    record.sync = outcome.sync;
+    store(record);
+
+    let (job, id) = (&record.job, record.id);
+    match record.status {
+        JobStatus::Failed => event!(Level::ERROR, "Job {job} #{id} failed: {}", record.message),
+        _ => event!(Level::INFO, "Job {job} #{id} finished: {}", record.message),
+    }
+}
+
+/// Returns the recorded runs, newest first, optionally of a single job.
+pub fn list(job: Option<&str>, limit: Option<usize>) -> Vec<JobRun> {
+    let runs = RUNS.lock().unwrap_or_else(|e| e.into_inner());
+    runs.iter()
+        .filter(|run| job.is_none_or(|job| run.job == job))
+        .take(limit.unwrap_or(usize::MAX))
+        .cloned()
+        .collect()
+}
+
+/// Returns the recorded run with the given id.
+pub fn get(id: u64) -> Option<JobRun> {
+    let runs = RUNS.lock().unwrap_or_else(|e| e.into_inner());
+    runs.iter().find(|run| run.id == id).cloned()
+}
@@ -0,0 +1,318 @@
+//! # Scheduler Module
+//!
+//! Runs named jobs on cron schedules within the tokio runtime.
+//!
+//! ## Path
+//!
+//! cron_tasks/scheduler.rs
+//!
+//! # Description
+//!
+//! Each registered job gets its own task which sleeps until the next time
+//! its cron expression matches and then runs the job. Runs of the same job
+//! never overlap: a run that takes longer than the interval skips the
+//! occurrences it missed. Expressions are validated when a job is added.
+//!
+//! At runtime a job can be paused (skipping its scheduled runs until it's
+//! resumed), moved to another schedule, or triggered right away. Scheduled
+//! runs are skipped on followers (see the `leader` module) while triggered
+//! runs always execute, even when the job is paused. A run may ask to be
+//! followed by another one after a delay instead of on the schedule (see
+//! the `adaptive` module).
+//!
+//! ## Notes
+//!
+//! Expressions have six or seven fields (the seconds come first, the year
+//! is optional), i.e. `0 1/5 * * * *` runs every 5 minutes starting at 1
+//! minute past the hour. A helpful tool for building them is
+//! https://crontab.cronhub.io/
+//!
+//! Paused jobs and changed schedules only apply to this instance and are
+//! reset to the configured ones when the service restarts (schedules also
+//! when a config reload changes them).
+//!
+//! `shutdown` stops all jobs, waiting for the ones currently running to finish.
+
+use chrono::{DateTime, Utc};
+use cron::Schedule;
+use serde::Serialize;
+use std::future::Future;
+use std::pin::Pin;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::{watch, Notify};
+use tokio::task::JoinHandle;
+use tracing::{event, Level};
+
+use super::runs::{self, JobOutcome, JobRun, JobStatus};
+use crate::leader;
+
+/// The future a job run returns, resolving to its outcome.
+pub type JobFuture = Pin<Box<dyn Future<Output = JobOutcome> + Send>>;
+
+/// Starts a run of a job.
+pub type JobFn = Arc<dyn Fn() -> JobFuture + Send + Sync>;
+
+/// Parses and validates a cron expression.
+pub fn parse_schedule(expression: &str) -> Result<Schedule, String> {
+    Schedule::from_str(expression.trim())
+        .map_err(|e| format!("Invalid cron expression '{expression}': {e}"))
+}
+
+/// The current state of a scheduled job.
+#[derive(Serialize, Debug, Clone)]
+pub struct JobState {
+    pub name: String,
+    /// The cron expression the job runs on.
+    pub schedule: String,
+    pub paused: bool,
+    pub running: bool,
+    /// When the job runs next (none while paused).
+    pub next_run: Option<DateTime<Utc>>,
+}
+
+/// A job registered on the `Scheduler`.
+pub struct ScheduledJob {
+    name: String,
+    run: JobFn,
+    /// The cron expression along with its parsed schedule.
+    schedule: Mutex<(String, Schedule)>,
+    paused: AtomicBool,
+    /// When the job runs next as requested by its last run, overriding the schedule.
+    next_override: Mutex<Option<DateTime<Utc>>>,
+    /// Held while the job runs so runs never overlap.
+    running: tokio::sync::Mutex<()>,
+    /// Wakes up the job's task when it's paused, resumed, rescheduled or ran.
+    changed: Notify,
+}
+
+impl ScheduledJob {
+    /// Returns when the job runs next, unless it's paused.
+    fn next_run(&self) -> Option<DateTime<Utc>> {
+        if self.paused.load(Ordering::SeqCst) {
+            return None;
+        }
+        if let Some(next) = *self.next_override.lock().unwrap_or_else(|e| e.into_inner()) {
+            return Some(next);
+        }
+        let schedule = self.schedule.lock().unwrap_or_else(|e| e.into_inner());
+        schedule.1.upcoming(Utc).next()
+    }
+
+    /// Returns the current state of the job.
+    pub fn state(&self) -> JobState {
+        let expression = self
+            .schedule
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .0
+            .clone();
+        JobState {
+            name: self.name.clone(),
+            schedule: expression,
+            paused: self.paused.load(Ordering::SeqCst),
+            running: self.running.try_lock().is_err(),
+            next_run: self.next_run(),
+        }
+    }
+
+    /// Skips the scheduled runs until resumed.
+    pub fn pause(&self) -> JobState {
+        if !self.paused.swap(true, Ordering::SeqCst) {
+            event!(Level::INFO, "Paused job {}", self.name);
+            self.changed.notify_one();
+        }
+        self.state()
+    }
+
+    /// Runs the job on its schedule again.
+    pub fn resume(&self) -> JobState {
+        if self.paused.swap(false, Ordering::SeqCst) {
+            event!(Level::INFO, "Resumed job {}", self.name);
+            self.set_next_override(None);
+            self.changed.notify_one();
+        }
+        self.state()
+    }
+
+    /// Validates the expression and runs the job on it from now on.
+    pub fn reschedule(&self, expression: &str) -> Result<JobState, String> {
+        let schedule = parse_schedule(expression)?;
+        let expression = expression.trim().to_owned();
+        event!(
+            Level::INFO,
+            "Rescheduling job {} :: (pattern = {expression})",
+            self.name
+        );
+        *self.schedule.lock().unwrap_or_else(|e| e.into_inner()) = (expression, schedule);
+        self.set_next_override(None);
+        self.changed.notify_one();
+        Ok(self.state())
+    }
+
+    /// Runs the job right away, returning the recorded run or `None` when
+    /// the job is already running.
+    pub async fn trigger(&self) -> Option<JobRun> {
+        self.run(runs::TRIGGER_API).await
+    }
+
+    fn set_next_override(&self, next: Option<DateTime<Utc>>) {
+        *self.next_override.lock().unwrap_or_else(|e| e.into_inner()) = next;
+    }
+
+    async fn run(&self, trigger: &str) -> Option<JobRun> {
+        let _running = self.running.try_lock().ok()?;
+        let mut record = runs::start(&self.name, trigger);
+        let outcome = match trigger == runs::TRIGGER_SCHEDULE && !leader::is_leader() {
+            true => JobOutcome::new(JobStatus::Skipped, "Skipped, not the leader"),
+            false => (self.run)().await,
+        };
+
+        let next = outcome
+            .next_delay
+            .map(|delay| Utc::now() + chrono::Duration::from_std(delay).unwrap_or_default());
+        self.set_next_override(next);
+        self.changed.notify_one();
+
+        runs::finish(&mut record, outcome);
+        Some(record)
+    }
+}
+
+/// Runs the registered jobs until shut down.
+pub struct Scheduler {
+    shutdown: watch::Sender<bool>,
+    jobs: Vec<Arc<ScheduledJob>>,
+    handles: Mutex<Vec<(String, JoinHandle<()>)>>,
+}
+
+impl Scheduler {
+    /// Creates a new `Scheduler` struct without any jobs.
+    pub fn new() -> Scheduler {
+        let (shutdown, _) = watch::channel(false);
+        Scheduler {
+            shutdown,
+            jobs: vec![],
+            handles: Mutex::new(vec![]),
+        }
+    }
+
+    /// Validates the expression and starts running the job on its schedule.
+    pub fn add(&mut self, name: &str, expression: &str, run: JobFn) -> Result<(), String> {
+        let schedule = parse_schedule(expression)?;
+        let job = Arc::new(ScheduledJob {
+            name: name.to_owned(),
+            run,
+            schedule: Mutex::new((expression.trim().to_owned(), schedule)),
+            paused: AtomicBool::new(false),
+            next_override: Mutex::new(None),
+            running: tokio::sync::Mutex::new(()),
+            changed: Notify::new(),
+        });
+        let mut shutdown = self.shutdown.subscribe();
+
+        event!(
+            Level::INFO,
+            "Scheduling job {name} :: (pattern = {expression})"
+        );
+        let scheduled = job.clone();
+        let handle = tokio::spawn(async move {
+            let job = scheduled;
+            let mut count: u64 = 0;
+            loop {
+                let next = job.next_run();
+                let wait = async {
+                    match next {
+                        Some(next) => {
+                            tokio::time::sleep((next - Utc::now()).to_std().unwrap_or_default())
+                                .await
+                        }
+                        // paused (or no upcoming runs), wait for a change
+                        None => std::future::pending().await,
+                    }
+                };
+                tokio::select! {
+                    _ = wait => {}
+                    _ = job.changed.notified() => continue,
+                    _ = shutdown.changed() => return,
+                }
+
+                count += 1;
+                event!(
+                    Level::INFO,
+                    "Executing job {} #{count} - {}",
+                    job.name,
+                    Utc::now().to_rfc3339()
+                );
+                if job.run(runs::TRIGGER_SCHEDULE).await.is_none() {
+                    event!(
+                        Level::INFO,
+                        "Skipping job {}, a run is in progress",
+                        job.name
+                    );
+                }
+                if *shutdown.borrow() {
+                    return;
+                }
+            }
+        });
+        self.jobs.push(job);
+        self.handles
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push((name.to_owned(), handle));
+        Ok(())
+    }
+
+    /// Returns the job with the given name.
+    pub fn get(&self, name: &str) -> Option<Arc<ScheduledJob>> {
+        self.jobs.iter().find(|job| job.name == name).cloned()
+    }
+
+    /// Returns the current state of all jobs.
+    pub fn states(&self) -> Vec<JobState> {
+        self.jobs.iter().map(|job| job.state()).collect()
+    }
+
+    /// Stops all jobs, waiting for running ones to finish.
+    pub async fn shutdown(&self) {
+        let _ = self.shutdown.send(true);
+        let handles = std::mem::take(&mut *self.handles.lock().unwrap_or_else(|e| e.into_inner()));
+        for (name, handle) in handles {
+            if let Err(err) = handle.await {
+                event!(Level::ERROR, "Job {name} stopped unexpectedly: {err:?}");
+            }
+        }
+        event!(Level::INFO, "Scheduler stopped");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Timelike;
+
+    #[test]
+    fn parse_schedule_accepts_six_fields_and_an_optional_year() {
+        assert!(parse_schedule("0 1/5 * * * *").is_ok());
+        assert!(parse_schedule(" 0 30 * * * * ").is_ok());
+        assert!(parse_schedule("0 0 0 1 1 * 2099").is_ok());
+    }
+
+    #[test]
+    fn parse_schedule_rejects_invalid_expressions() {
+        for expression in ["", "*/5 * * * *", "0 61 * * * *", "every minute"] {
+            let err = parse_schedule(expression).unwrap_err();
+            assert!(err.contains(&format!("'{expression}'")), "{err}");
+        }
+    }
+
+    #[test]
+    fn parse_schedule_gives_upcoming_times() {
+        let schedule = parse_schedule("0 1/5 * * * *").unwrap();
+        let next = schedule.upcoming(Utc).next().unwrap();
+        assert_eq!(next.second(), 0);
+        assert_eq!(next.minute() % 5, 1);
+    }
+}
@@ -0,0 +1,258 @@
+//! # Leader Module
+//!
+//! Elects a single replica to run the scheduled jobs.
+//!
+//! ## Path
+//!
+//! leader.rs
+//!
+//! # Description
+//!
+//! When running several replicas, only the one holding the leader lease
+//! runs the scheduled sync and archive jobs while the others (followers)
+//! keep serving reads. The lease is held for `LS_LEADER_LEASE_TTL` seconds
+//! and renewed every third of it, so when the leader dies a follower takes
+//! over once the lease expired. Depending on `LS_LEADER_ELECTION` the lease
+//! is kept in:
+//!
+//! - `none`: (default) no election, every instance is the leader
+//! - `redis`: the `{REDIS_KEY_NAME}:leader` key (set with `NX PX`)
+//! - `file`: `.leader.lease` in `LOG_DIRECTORY` (for replicas sharing a volume)
+//!
+//! ## Notes
+//!
+//! An instance that can't renew its lease (i.e. Redis is unreachable) steps
+//! down right away rather than risk two leaders.
+
+use chrono::Utc;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{info, warn};
+
+use crate::caching::RedisPool;
+use crate::env_config::{
+    EnvConfig, LOG_DIRECTORY, LS_LEADER_ELECTION, LS_LEADER_LEASE_TTL, REDIS_KEY_NAME,
+};
+use crate::storage::file_lock;
+
+/// Whether this instance currently holds the lease.
+static IS_LEADER: AtomicBool = AtomicBool::new(false);
+
+/// Identifies this instance as the lease holder.
+static INSTANCE_ID: Lazy<String> = Lazy::new(|| {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or(0);
+    let hostname = std::env::var("HOSTNAME").unwrap_or_default();
+    format!("{hostname}-{}-{nanos}", std::process::id())
+});
+
+/// Extends the lease only while it's still held by the given holder.
+const RENEW_LEASE_SCRIPT: &str = r#"
+if redis.call("GET", KEYS[1]) == ARGV[1] then
+    return redis.call("PEXPIRE", KEYS[1], ARGV[2])
+else
+    return 0
+end
+"#;
+
+/// Deletes the lease only while it's still held by the given holder.
+const RELEASE_LEASE_SCRIPT: &str = r#"
+if redis.call("GET", KEYS[1]) == ARGV[1] then
+    return redis.call("DEL", KEYS[1])
+else
+    return 0
+end
+"#;
+
+/// Filename of the lease under `LOG_DIRECTORY` for the `file` election.
+const LEASE_FILE_NAME: &str = ".leader.lease";
+
+/// Name of the file lock held while reading and writing the lease file.
+const LEASE_LOCK_NAME: &str = "_leader";
+
+/// Contents of the lease file.
+#[derive(Serialize, Deserialize, Debug)]
+struct FileLease {
+    holder: String,
+    expires_at: i64,
+}
+
+/// Returns whether this instance should run the scheduled jobs.
+pub fn is_leader() -> bool {
+    IS_LEADER.load(Ordering::SeqCst)
+}
+
+fn get_election() -> String {
+    EnvConfig::global().get_val(LS_LEADER_ELECTION)
+}
+
+fn get_lease_ttl_ms() -> u64 {
+    EnvConfig::global()
+        .get_val(LS_LEADER_LEASE_TTL)
+        .parse::<u64>()
+        .unwrap_or(15)
+        .max(1)
+        * 1000
+}
+
+fn get_redis_key() -> String {
+    format!("{}:leader", EnvConfig::global().get_val(REDIS_KEY_NAME))
+}
+
+fn get_lease_path() -> PathBuf {
+    Path::new(&EnvConfig::global().get_val(LOG_DIRECTORY)).join(LEASE_FILE_NAME)
+}
+
+/// Renews the lease in Redis, or takes it when nobody holds it.
+async fn try_redis_lease(redis: &RedisPool, ttl_ms: u64) -> Result<bool, String> {
+    let key = get_redis_key();
+    let renewed: i32 = redis
+        .query(
+            redis::cmd("EVAL")
+                .arg(RENEW_LEASE_SCRIPT)
+                .arg(1)
+                .arg(&key)
+                .arg(INSTANCE_ID.as_str())
+                .arg(ttl_ms),
+        )
+        .await
+        .map_err(|e| format!("Unable to renew lease {key}: {e:?}"))?;
+    if renewed == 1 {
+        return Ok(true);
+    }
+
+    let acquired: Option<String> = redis
+        .query(
+            redis::cmd("SET")
+                .arg(&key)
+                .arg(INSTANCE_ID.as_str())
+                .arg("NX")
+                .arg("PX")
+                .arg(ttl_ms),
+        )
+        .await
+        .map_err(|e| format!("Unable to acquire lease {key}: {e:?}"))?;
+    Ok(acquired.is_some())
+}
+
+/// Renews the lease file, or takes it when it expired or doesn't exist.
+async fn try_file_lease(ttl_ms: u64) -> Result<bool, String> {
+    let path = get_lease_path();
+    let _lock = file_lock::acquire(LEASE_LOCK_NAME)
+        .await
+        .map_err(|e| format!("Unable to lock the lease: {e:?}"))?;
+
+    let now = Utc::now().timestamp_millis();
+    let current = tokio::fs::read_to_string(&path)
+        .await
+        .ok()
+        .and_then(|contents| serde_json::from_str::<FileLease>(&contents).ok());
+    if let Some(lease) = current {
+        if lease.holder != *INSTANCE_ID && lease.expires_at > now {
+            return Ok(false);
+        }
+    }
+
+    let lease = FileLease {
+        holder: INSTANCE_ID.clone(),
+        expires_at: now + ttl_ms as i64,
+    };
+    let write = async {
+        let tmp_path = path.with_extension("lease.tmp");
+        tokio::fs::write(&tmp_path, serde_json::to_string(&lease)?).await?;
+        tokio::fs::rename(&tmp_path, &path).await
+    };
+    write
+        .await
+        .map_err(|e| format!("Unable to write the lease: {e:?}"))?;
+    Ok(true)
+}
+
+/// Starts taking part in the election configured via `LS_LEADER_ELECTION`,
+/// renewing (or trying to take) the lease in the background.
+pub fn start(redis: Arc<RedisPool>) -> Result<(), String> {
+    let election = get_election();
+    match election.as_str() {
+        "none" => {
+            IS_LEADER.store(true, Ordering::SeqCst);
+            return Ok(());
+        }
+        "redis" | "file" => {}
+        other => return Err(format!("Unknown leader election '{other}'")),
+    }
+
+    let ttl_ms = get_lease_ttl_ms();
+    info!(
+        "Starting {election} leader election as {}",
+        INSTANCE_ID.as_str()
+    );
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_millis(ttl_ms / 3));
+        loop {
+            interval.tick().await;
+            let result = match election.as_str() {
+                "redis" => try_redis_lease(&redis, ttl_ms).await,
+                _ => try_file_lease(ttl_ms).await,
+            };
+            let leader = result.unwrap_or_else(|err| {
+                warn!("Warning: Stepping down as leader: {err}");
+                false
+            });
+            if IS_LEADER.swap(leader, Ordering::SeqCst) != leader {
+                match leader {
+                    true => info!("Became the leader, running scheduled jobs"),
+                    false => info!("Following another leader, skipping scheduled jobs"),
+                }
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Gives up the lease (if held) so a follower can take over right away.
+pub async fn release(redis: &RedisPool) {
+    if !IS_LEADER.swap(false, Ordering::SeqCst) {
+        return;
+    }
+    let result = match get_election().as_str() {
+        "redis" => redis
+            .query::<i32>(
+                redis::cmd("EVAL")
+                    .arg(RELEASE_LEASE_SCRIPT)
+                    .arg(1)
+                    .arg(get_redis_key())
+                    .arg(INSTANCE_ID.as_str()),
+            )
+            .await
+            .map(|_| ())
+            .map_err(|e| format!("{e:?}")),
+        "file" => match file_lock::acquire(LEASE_LOCK_NAME).await {
+            Ok(_lock) => {
+                let path = get_lease_path();
+                let held = tokio::fs::read_to_string(&path)
+                    .await
+                    .ok()
+                    .and_then(|contents| serde_json::from_str::<FileLease>(&contents).ok())
+                    .is_some_and(|lease| lease.holder == *INSTANCE_ID);
+                match held {
+                    true => tokio::fs::remove_file(&path)
+                        .await
+                        .map_err(|e| format!("{e:?}")),
+                    false => Ok(()),
+                }
+            }
+            Err(err) => Err(format!("{err:?}")),
+        },
+        _ => Ok(()),
+    };
+    match result {
+        Ok(()) => info!("Released the leader lease"),
+        Err(err) => warn!("Warning: Unable to release the leader lease: {err}"),
+    }
+}
@@ -27,20 +27,31 @@
 //! - `NRLS_API_KEY`: New Relic API Key
 //! - `REDIS_URL`: Redis URL with port
 //! - `LS_SVC_PORT`: (optional) App server port (defaults to `3333`)
+//!
+//! ## Commands
+//!
+//! Without a command (or with `serve`) the server starts, see the `cli`
+//! module for the one-off commands (`log-scraper --help`).
 
+use crate::caching::RedisPool;
 use crate::env_config::{EnvConfig, CONFIG, LOG_DIRECTORY, LS_SVC_PORT};
 use actix_cors::Cors;
 use actix_files as fs;
 use actix_web::{middleware::Logger, web, web::Data, App, HttpServer};
+use clap::Parser;
+use std::sync::Arc;
 use tokio::sync::Mutex;
 use tracing::{event, instrument, Level};
-use tracing_subscriber::fmt::format;
+use tracing_subscriber::fmt::{format, writer::BoxMakeWriter};
 
 mod api;
 mod caching;
+mod cli;
 mod cron_tasks;
 mod env_config;
+mod leader;
 mod new_relic;
+mod reload;
 mod scraper;
 mod storage;
 
@@ -49,20 +60,36 @@ mod storage;
 #[derive(Debug)]
 pub struct LogScraperState {
     last_seen: Mutex<String>, // last seen log timestamp in milliseconds
+    redis: Arc<RedisPool>,    // shared connection for caching and locks
 }
 
 #[actix_web::main]
 #[instrument(name = "log_scraper")]
 async fn main() -> std::io::Result<()> {
+    // parse the command line (exits with the usage on invalid arguments)
+    let args = cli::Cli::parse();
+    let command = args.command.filter(|c| !matches!(c, cli::Command::Serve));
+
     // register a log subscriber to print events & messages to stdout
+    // (stderr for one-off commands, keeping stdout for their output)
+    let writer = match command {
+        Some(_) => BoxMakeWriter::new(std::io::stderr),
+        None => BoxMakeWriter::new(std::io::stdout),
+    };
     tracing_subscriber::fmt()
         .compact()
+        .with_writer(writer)
         // exclude fields (except message) for now
         .fmt_fields(format::debug_fn(|writer, field, field_data| {
             if field.to_string() != "message" {
                 return Ok(());
             }
-            write!(writer, "{field_data:?}")
+            // never print secrets, whatever is logged
+            write!(
+                writer,
+                "{}",
+                env_config::redact_secrets(&format!("{field_data:?}"))
+            )
         }))
         // .with_file(true)
         // .with_line_number(true)
@@ -70,22 +97,50 @@ async fn main() -> std::io::Result<()> {
         // .with_target(true)
         .init();
 
-    // initialize the environment config
-    match CONFIG.set(EnvConfig::new()) {
-        Ok(_) => event!(Level::INFO, "Loaded config successfully"),
-        Err(_) => panic!("Error loading config!"),
+    // initialize the config, refusing to start with invalid values
+    let config = EnvConfig::load().map_err(|errors| {
+        errors
+            .iter()
+            .for_each(|err| event!(Level::ERROR, "Invalid config: {err}"));
+        std::io::Error::other(format!("Invalid config:\n  {}", errors.join("\n  ")))
+    })?;
+    CONFIG.store(Some(Arc::new(config)));
+    event!(Level::INFO, "Loaded config successfully");
+    EnvConfig::global().log_config();
+
+    // setup our logging storage backend
+    storage::init().await?;
+
+    // setup the shared redis connection (connected on first use)
+    let redis = Arc::new(RedisPool::new().map_err(std::io::Error::other)?);
+
+    // setup where the last seen timestamp is kept
+    caching::init(redis.clone()).await?;
+
+    // run a one-off command instead of the server when one is given
+    if let Some(command) = command {
+        return cli::run(command, redis)
+            .await
+            .map_err(std::io::Error::other);
     }
 
-    // setup our logging storage area
-    storage::ensure_log_directory().await?;
+    // fill an empty search index from the stored logs
+    storage::search_index::start_rebuild();
 
     // create our app state
     let app_state: Data<LogScraperState> = Data::new(LogScraperState {
         last_seen: Mutex::new("".to_owned()),
+        redis,
     });
 
+    // take part in the leader election (scheduled jobs only run on the leader)
+    leader::start(app_state.redis.clone()).map_err(std::io::Error::other)?;
+
     // start up cron jobs
-    cron_tasks::start(app_state.clone());
+    let scheduler = Data::new(cron_tasks::start(app_state.clone()).map_err(std::io::Error::other)?);
+
+    // reload the config on SIGHUP or when the config file changes
+    reload::start(scheduler.clone()).map_err(std::io::Error::other)?;
 
     // get server port from environment variables or defaults
     let port = EnvConfig::global().get_val(LS_SVC_PORT);
@@ -97,7 +152,9 @@ async fn main() -> std::io::Result<()> {
     event!(Level::INFO, "Starting server on port {port}");
     match port.parse::<u16>() {
         Ok(port_number) => {
-            HttpServer::new(move || {
+            let server_state = app_state.clone();
+            let server_scheduler = scheduler.clone();
+            let result = HttpServer::new(move || {
                 App::new()
                     // add cors headers
                     .wrap(Cors::permissive())
@@ -106,8 +163,10 @@ async fn main() -> std::io::Result<()> {
                             .wrap(Logger::new(api_logger_pattern))
                             .service(
                                 // allow viewing log files directly
+                                // (hidden files such as manifests and indices aren't served)
                                 fs::Files::new("/", EnvConfig::global().get_val(LOG_DIRECTORY))
-                                    .show_files_listing(),
+                                    .show_files_listing()
+                                    .redirect_to_slash_directory(),
                             ),
                     )
                     .service(
@@ -132,23 +191,63 @@ async fn main() -> std::io::Result<()> {
                     .service(
                         web::scope("/logs")
                             .app_data(app_state.clone())
+                            .app_data(scheduler.clone())
                             .wrap(Logger::new(api_logger_pattern))
                             .service(api::logs_api::sync_logs_endpoint)
                             .service(api::logs_api::get_log_list_endpoint)
+                            .service(api::logs_api::search_logs_endpoint)
+                            .service(api::logs_api::export_logs_endpoint)
+                            .service(api::logs_api::bundle_logs_endpoint)
+                            .service(api::logs_api::export_log_endpoint)
+                            .service(api::logs_api::download_log_endpoint)
+                            .service(api::logs_api::verify_log_endpoint)
                             .service(api::logs_api::delete_log_endpoint)
                             .service(api::logs_api::get_log_contents_endpoint),
                     )
+                    .service(
+                        web::scope("/jobs")
+                            .app_data(scheduler.clone())
+                            .wrap(Logger::new(api_logger_pattern))
+                            .service(api::jobs_api::get_jobs_endpoint)
+                            .service(api::jobs_api::get_schedules_endpoint)
+                            .service(api::jobs_api::get_schedule_endpoint)
+                            .service(api::jobs_api::pause_job_endpoint)
+                            .service(api::jobs_api::resume_job_endpoint)
+                            .service(api::jobs_api::reschedule_job_endpoint)
+                            .service(api::jobs_api::trigger_job_endpoint)
+                            .service(api::jobs_api::get_job_endpoint),
+                    )
+                    .service(
+                        web::scope("/checkpoints")
+                            .app_data(app_state.clone())
+                            .wrap(Logger::new(api_logger_pattern))
+                            .service(api::checkpoints_api::get_checkpoints_endpoint)
+                            .service(api::checkpoints_api::set_checkpoint_endpoint)
+                            .service(api::checkpoints_api::rewind_checkpoint_endpoint),
+                    )
                     .service(
                         web::scope("")
+                            .app_data(app_state.clone())
+                            .app_data(scheduler.clone())
                             .service(api::index_api::health_check_endpoint)
+                            .service(api::index_api::readiness_endpoint)
                             .service(api::index_api::version_endpoint)
+                            .service(api::index_api::config_endpoint)
+                            .service(api::index_api::reload_config_endpoint)
                             // static files for web scope need to be served at root
-                            .service(fs::Files::new("/static", "./build/static"))
+                            .service(fs::Files::new("/static", "./build/static")),
                     )
             })
             .bind(("0.0.0.0", port_number))?
             .run()
-            .await
+            .await;
+
+            // the server stopped (i.e. on SIGTERM), let running jobs finish
+            server_scheduler.shutdown().await;
+
+            // let a follower take over right away
+            leader::release(&server_state.redis).await;
+            result
         }
         Err(err) => panic!("Error starting the server: {err:?}"),
     }
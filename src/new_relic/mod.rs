@@ -9,15 +9,40 @@
 //! # Description
 //!
 //! Allows fetching logs from New Relic Graph QL API
+//!
+//! ## Notes
+//!
+//! Logs are fetched oldest first, at most `NRLS_FETCH_LIMIT` per request (up
+//! to the 5000 rows NRQL allows), so a sync that hits the limit picks up where
+//! it left off on the next poll. As the watermark moves past the latest
+//! fetched millisecond, the logs of that millisecond are left for the next
+//! poll when the limit is hit (more of them may be past the limit).
+//!
+//! Attributes besides the standard log fields are dropped unless
+//! `NRLS_KEEP_ATTRIBUTES` is enabled, keeping the stored line format as is.
 
-mod types;
+pub mod types;
 
-use crate::env_config::{EnvConfig, NRLS_ACCOUNT_ID, NRLS_API_KEY};
+use crate::env_config::{
+    EnvConfig, NRLS_ACCOUNT_ID, NRLS_API_KEY, NRLS_FETCH_LIMIT, NRLS_KEEP_ATTRIBUTES,
+};
 use crate::new_relic::types::{NewRelicLogItem, NrqlResponse};
 use chrono::Duration;
 use reqwest::header::{HeaderMap, HeaderValue};
 use tracing::{event, instrument, trace, warn, Level};
 
+/// Maximum number of rows NRQL returns for a query.
+const NRQL_MAX_LIMIT: usize = 5000;
+
+/// Returns the maximum number of logs fetched per request.
+pub fn fetch_limit() -> usize {
+    EnvConfig::global()
+        .get_val(NRLS_FETCH_LIMIT)
+        .parse::<usize>()
+        .unwrap_or(100)
+        .clamp(1, NRQL_MAX_LIMIT)
+}
+
 /// Creates a New Relic Graphql Request Payload with the given Account
 /// ID and simple NRQL expression.
 ///
@@ -41,6 +66,33 @@ fn create_nrql_payload(account_id: &str, query: &str) -> String {
     result_str
 }
 
+/// Logs fetched by a single request.
+#[derive(Debug, Default)]
+pub struct FetchedLogs {
+    pub logs: Vec<NewRelicLogItem>,
+    /// Whether the fetch limit was reached, so more logs are likely waiting.
+    pub hit_limit: bool,
+}
+
+/// Drops the logs sharing the latest millisecond of a full batch, as more
+/// logs of that millisecond may be past the limit, so the next fetch starts
+/// at it. Keeps them all when every log shares the same millisecond.
+fn drop_latest_millisecond(logs: &mut Vec<NewRelicLogItem>) {
+    let Some(latest) = logs.last().map(|l| l.timestamp.timestamp_millis()) else {
+        return;
+    };
+    match logs
+        .iter()
+        .rposition(|l| l.timestamp.timestamp_millis() != latest)
+    {
+        Some(last_earlier) => logs.truncate(last_earlier + 1),
+        None => warn!(
+            "Warning: All {} fetched logs share the timestamp {latest}, any more logs of it are skipped (raise {NRLS_FETCH_LIMIT})",
+            logs.len()
+        ),
+    }
+}
+
 #[derive(Debug)]
 pub struct NewRelic {}
 
@@ -55,7 +107,7 @@ impl NewRelic {
     /// Requires Account ID (`NRLS_ACCOUNT_ID`) and API key
     /// (`NRLS_API_KEY`) to be set via environment variables.
     #[instrument(name = "logs_since")]
-    pub async fn logs_since(&self, timestamp: &str) -> Result<Vec<NewRelicLogItem>, String> {
+    pub async fn logs_since(&self, timestamp: &str) -> Result<FetchedLogs, String> {
         // fetch new relic logs since last timestamp
         let resp = self.get_logs(timestamp).await?;
 
@@ -63,13 +115,22 @@ impl NewRelic {
         let mut logs = resp.data.actor.account.nrql.results;
 
         if logs.is_empty() {
-            return Ok(logs);
+            return Ok(FetchedLogs::default());
         }
 
         // ensure logs are sorted by timestamp
-        logs.sort_by(|a, b| a.timestamp.cmp(&b.timestamp));
+        logs.sort_by_key(|l| l.timestamp);
 
-        Ok(logs)
+        let hit_limit = logs.len() >= fetch_limit();
+        if hit_limit {
+            drop_latest_millisecond(&mut logs);
+        }
+
+        if EnvConfig::global().get_val(NRLS_KEEP_ATTRIBUTES) != "true" {
+            logs.iter_mut().for_each(|l| l.attributes.clear());
+        }
+
+        Ok(FetchedLogs { logs, hit_limit })
     }
 
     // Makes an http call to fetch logs from New Relic API.
@@ -88,7 +149,10 @@ impl NewRelic {
             timestamp_millis.to_owned()
         };
 
-        let log_query = format!("SELECT * FROM Log SINCE {since}");
+        let log_query = format!(
+            "SELECT * FROM Log SINCE {since} ORDER BY timestamp ASC LIMIT {}",
+            fetch_limit()
+        );
         let nrql_payload: String = create_nrql_payload(&nrls_id, &log_query);
         trace!("Constructed query: {nrql_payload}");
 
@@ -159,7 +223,7 @@ impl NewRelic {
         let mut logs_copy = log_results.to_owned();
 
         // sort and print the logs
-        logs_copy.sort_by(|a, b| a.timestamp.cmp(&b.timestamp));
+        logs_copy.sort_by_key(|l| l.timestamp);
         logs_copy.iter().for_each(|row| {
             let t = row.timestamp.to_rfc3339();
             let mut log_message = row.message.clone();
@@ -183,3 +247,43 @@ impl NewRelic {
         format!("{}", d.timestamp_millis())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{TimeZone, Utc};
+    use serde_json::Map;
+
+    fn log_at(millis: i64) -> NewRelicLogItem {
+        NewRelicLogItem {
+            logger_name: "api".to_owned(),
+            request_id: "r1".to_owned(),
+            logtype: "info".to_owned(),
+            message: format!("at {millis}"),
+            message_id: "m1".to_owned(),
+            project: "billing".to_owned(),
+            timestamp: Utc.timestamp_millis_opt(millis).unwrap(),
+            attributes: Map::new(),
+        }
+    }
+
+    fn millis(logs: &[NewRelicLogItem]) -> Vec<i64> {
+        logs.iter()
+            .map(|l| l.timestamp.timestamp_millis())
+            .collect()
+    }
+
+    #[test]
+    fn drop_latest_millisecond_leaves_it_for_the_next_fetch() {
+        let mut logs = vec![log_at(1), log_at(2), log_at(3), log_at(3)];
+        drop_latest_millisecond(&mut logs);
+        assert_eq!(millis(&logs), [1, 2]);
+    }
+
+    #[test]
+    fn drop_latest_millisecond_keeps_a_single_millisecond() {
+        let mut logs = vec![log_at(5), log_at(5)];
+        drop_latest_millisecond(&mut logs);
+        assert_eq!(millis(&logs), [5, 5]);
+    }
+}
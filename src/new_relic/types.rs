@@ -12,6 +12,7 @@
 
 use chrono::{serde::ts_milliseconds, DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
 
 #[derive(Deserialize, Debug, Serialize)]
 #[serde(rename_all = "camelCase")]
@@ -24,6 +25,10 @@ pub struct NewRelicLogItem {
     pub project: String,
     #[serde(with = "ts_milliseconds")]
     pub timestamp: DateTime<Utc>,
+    /// Any other attributes of the log record (they vary per log source),
+    /// only kept when `NRLS_KEEP_ATTRIBUTES` is enabled.
+    #[serde(flatten)]
+    pub attributes: Map<String, Value>,
 }
 
 #[derive(Deserialize, Serialize)]
@@ -61,6 +66,7 @@ impl Clone for NewRelicLogItem {
             message_id: self.message_id.clone(),
             project: self.project.clone(),
             timestamp: self.timestamp,
+            attributes: self.attributes.clone(),
         }
     }
 }
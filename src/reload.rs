@@ -0,0 +1,123 @@
+//! # Reload Module
+//!
+//! Reloads the config without restarting the service.
+//!
+//! ## Path
+//!
+//! reload.rs
+//!
+//! # Description
+//!
+//! The config is loaded again (see `EnvConfig::reload`) when:
+//!
+//! - the service receives `SIGHUP` (i.e. `kill -HUP <pid>`)
+//! - the `LS_CONFIG_FILE` or a file a secret is read from (`*_FILE`)
+//!   changed, checked every few seconds
+//! - the `POST /config/reload` endpoint is called
+//!
+//! The new values apply from the next time they're read: the next sync picks
+//! up the New Relic, storage and polling settings while changed job
+//! schedules are applied to the scheduler right away.
+//!
+//! ## Notes
+//!
+//! An invalid config is rejected with its errors logged, keeping the current
+//! one. Rescheduling a job via the API lasts until the next reload changing
+//! its schedule.
+
+use actix_web::web::Data;
+use std::collections::HashMap;
+use std::fs;
+use std::time::{Duration, SystemTime};
+use tokio::signal::unix::{signal, SignalKind};
+use tracing::{event, Level};
+
+use crate::cron_tasks::{self, scheduler::Scheduler};
+use crate::env_config::{EnvConfig, ReloadReport, LS_CONFIG_FILE};
+
+/// How often the config and secret files are checked for changes.
+const CONFIG_FILE_POLL: Duration = Duration::from_secs(5);
+
+/// Reloads the config and applies changed job schedules, keeping the
+/// current config when the new one is invalid.
+pub fn reload(scheduler: &Scheduler) -> Result<ReloadReport, Vec<String>> {
+    event!(Level::INFO, "Reloading config");
+    match EnvConfig::reload() {
+        Ok(report) => {
+            cron_tasks::apply_config(scheduler, &report);
+            event!(
+                Level::INFO,
+                "Reloaded config :: (changed = {}, restart required = {})",
+                report.changed.len(),
+                report.restart_required.len()
+            );
+            Ok(report)
+        }
+        Err(errors) => {
+            errors
+                .iter()
+                .for_each(|err| event!(Level::ERROR, "Invalid config: {err}"));
+            event!(
+                Level::ERROR,
+                "Rejected config reload, keeping the current config"
+            );
+            Err(errors)
+        }
+    }
+}
+
+/// Returns when the file was last modified, if it can be read.
+fn modified_at(path: &str) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|meta| meta.modified()).ok()
+}
+
+/// Returns the config file and the files secrets are currently read from.
+fn watched_files(config_path: &str) -> Vec<String> {
+    let mut paths = EnvConfig::global().secret_file_paths();
+    if !config_path.is_empty() {
+        paths.push(config_path.to_owned());
+    }
+    paths
+}
+
+/// Starts reloading the config on `SIGHUP` and whenever the config file or
+/// a secret file changes.
+pub fn start(scheduler: Data<Scheduler>) -> Result<(), String> {
+    let mut hangup =
+        signal(SignalKind::hangup()).map_err(|e| format!("Unable to listen for SIGHUP: {e}"))?;
+    let config_path = EnvConfig::global().get_val(LS_CONFIG_FILE);
+
+    tokio::spawn(async move {
+        let mut last_modified: HashMap<String, Option<SystemTime>> = watched_files(&config_path)
+            .into_iter()
+            .map(|path| {
+                let modified = modified_at(&path);
+                (path, modified)
+            })
+            .collect();
+        let mut interval = tokio::time::interval(CONFIG_FILE_POLL);
+        loop {
+            tokio::select! {
+                _ = hangup.recv() => {
+                    event!(Level::INFO, "Received SIGHUP");
+                }
+                _ = interval.tick() => {
+                    let mut changed = None;
+                    for path in watched_files(&config_path) {
+                        let modified = modified_at(&path);
+                        if last_modified.get(&path) != Some(&modified) {
+                            last_modified.insert(path.clone(), modified);
+                            changed = Some(path);
+                        }
+                    }
+                    let Some(path) = changed else {
+                        continue;
+                    };
+                    event!(Level::INFO, "Config file {path} changed");
+                }
+            }
+            let _ = reload(&scheduler);
+        }
+    });
+    Ok(())
+}
@@ -1,10 +1,26 @@
 //! # Scraper Module
 //!
 //! Attempts to sync and persist logs found on the remote server.
+//!
+//! ## Notes
+//!
+//! Each batch is written per partition (see `storage::get_partition`). When
+//! some partitions fail, the watermark only advances up to the oldest log
+//! that wasn't written, and the partitions that were written remember their
+//! own watermark (in `.partition_watermarks.json` under `LOG_DIRECTORY`) so
+//! refetched logs aren't written to them twice.
 
 use actix_web::web::Data;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
 
-use crate::{caching, new_relic::NewRelic, storage, LogScraperState};
+use crate::{
+    caching::{self, history::CheckpointEntry},
+    env_config::{EnvConfig, LOG_DIRECTORY},
+    new_relic::{types::NewRelicLogItem, NewRelic},
+    storage, LogScraperState,
+};
 use tracing::{info, instrument, trace, warn};
 
 /// Saves the string using the caching module. Fails softly
@@ -19,21 +35,126 @@ async fn save_to_cache(val: String) {
     };
 }
 
+/// Filename of the partition watermarks under `LOG_DIRECTORY`.
+const PARTITION_WATERMARKS_NAME: &str = ".partition_watermarks.json";
+
+fn get_partition_watermarks_path() -> PathBuf {
+    PathBuf::from(EnvConfig::global().get_val(LOG_DIRECTORY)).join(PARTITION_WATERMARKS_NAME)
+}
+
+/// Reads the watermarks (in milliseconds) of partitions written past the
+/// shared watermark, empty when there are none.
+async fn read_partition_watermarks() -> BTreeMap<String, i64> {
+    match tokio::fs::read_to_string(get_partition_watermarks_path()).await {
+        Ok(contents) => serde_json::from_str(&contents).unwrap_or_else(|err| {
+            warn!("Warning: Unable to parse the partition watermarks: {err:?}");
+            BTreeMap::new()
+        }),
+        Err(_) => BTreeMap::new(),
+    }
+}
+
+/// Writes the partition watermarks atomically (via a temp file and rename),
+/// removing the file when there are none.
+async fn write_partition_watermarks(watermarks: &BTreeMap<String, i64>) -> tokio::io::Result<()> {
+    let path = get_partition_watermarks_path();
+    if watermarks.is_empty() {
+        return match tokio::fs::remove_file(&path).await {
+            Err(err) if err.kind() != tokio::io::ErrorKind::NotFound => Err(err),
+            _ => Ok(()),
+        };
+    }
+    let tmp_path = path.with_extension("json.tmp");
+    tokio::fs::write(&tmp_path, serde_json::to_string_pretty(watermarks)?).await?;
+    tokio::fs::rename(&tmp_path, &path).await
+}
+
+/// Name of the source the distributed sync lock is taken for.
+const SYNC_LOCK_SOURCE: &str = "sync";
+
+/// The outcome of a sync.
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct SyncReport {
+    /// Whether the sync was skipped since another instance was syncing.
+    pub skipped: bool,
+    pub logs_fetched: usize,
+    /// Whether the fetch limit was reached, so more logs are likely waiting.
+    pub hit_limit: bool,
+    /// Files the logs were written to.
+    pub files: Vec<String>,
+    pub watermark_before: String,
+    pub watermark_after: String,
+    /// Why no (or not all) logs were stored, leaving the watermark as is.
+    pub error: Option<String>,
+}
+
+impl SyncReport {
+    /// A sync that didn't write anything, leaving the watermark as is.
+    fn unchanged(watermark: String, error: Option<String>) -> SyncReport {
+        SyncReport {
+            watermark_before: watermark.clone(),
+            watermark_after: watermark,
+            error,
+            ..Default::default()
+        }
+    }
+}
+
 /// Runs the sync with thread safe caching of timestamp via LogScraperState.
+/// Only one instance syncs at a time (see `caching::acquire_lock`), others
+/// skip the run. Falls back to syncing without the distributed lock when
+/// Redis can't be reached or isn't used as the checkpoint backend, relying
+/// on the file locks in `storage`. Returns what the sync did.
 #[instrument(name = "run_sync")]
-pub async fn run_sync(data: Data<LogScraperState>) -> tokio::io::Result<()> {
+pub async fn run_sync(data: Data<LogScraperState>) -> SyncReport {
     // acquire lock on mutex
     let mut last_seen = data.last_seen.lock().await;
-    let t: String = (*last_seen.clone()).to_string();
+
+    let redis_lock = if caching::uses_redis() {
+        match caching::acquire_lock(&data.redis, SYNC_LOCK_SOURCE).await {
+            Ok(Some(lock)) => Some(lock),
+            Ok(None) => {
+                info!("Another instance is syncing, skipping this run");
+                return SyncReport {
+                    skipped: true,
+                    ..Default::default()
+                };
+            }
+            Err(err) => {
+                warn!("Warning: Syncing without a distributed lock: {err}");
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    // another instance may have synced since, so prefer the shared watermark
+    let t: String = match &redis_lock {
+        Some(_) => match caching::get_cached_val().await {
+            Ok(cached) if !cached.is_empty() => cached,
+            _ => (*last_seen.clone()).to_string(),
+        },
+        None => (*last_seen.clone()).to_string(),
+    };
 
     // run sync operation
     trace!("Sending value to log_scraper: {}", t);
-    let u = attempt_sync(t).await;
+    let report = attempt_sync(t).await;
+
+    if let Some(lock) = redis_lock {
+        if let Err(err) = lock.release(&data.redis).await {
+            warn!("Warning: {err}");
+        }
+    }
 
     // update the underlying mutex value
-    *last_seen = u.clone();
-    trace!("Updated LogScraperState with last_seen: {}", u);
-    Ok(())
+    *last_seen = report.watermark_after.clone();
+    trace!(
+        "Updated LogScraperState with last_seen: {}",
+        report.watermark_after
+    );
+    report
 }
 
 /// Attempts to sync local logs from the remote log service and saves them to disk.
@@ -41,7 +162,7 @@ pub async fn run_sync(data: Data<LogScraperState>) -> tokio::io::Result<()> {
 /// and can be passed in as a parameter or is read from the remote cache using the
 /// caching module.
 #[instrument(name = "attempt_sync")]
-async fn attempt_sync(timestamp_from_memory: String) -> String {
+async fn attempt_sync(timestamp_from_memory: String) -> SyncReport {
     // only hit the cache if needed for reading
     let last_seen = if !timestamp_from_memory.is_empty() {
         trace!("Using value of last_seen from memory: {timestamp_from_memory}");
@@ -58,33 +179,102 @@ async fn attempt_sync(timestamp_from_memory: String) -> String {
                 last_seen
             }
             Err(err) => {
+                // syncing from the start (7 days ago) would fetch stored logs again
                 warn!("Warning: An error occurred reading from cache: {err:?}");
-                "".to_owned()
+                return SyncReport::unchanged(
+                    "".to_owned(),
+                    Some(format!("Unable to read the watermark: {err}")),
+                );
             }
         }
     };
 
-    let new_watermark = run_new_relic_sync(last_seen).await;
+    let report = run_new_relic_sync(last_seen.clone()).await;
+    let new_watermark = report.watermark_after.clone();
 
     info!("Caching last_seen timestamp on remote: {new_watermark}");
     save_to_cache(new_watermark.clone()).await;
 
+    if report.logs_fetched > 0 && new_watermark != last_seen {
+        let entry = CheckpointEntry {
+            batch_size: report.logs_fetched,
+            files: report.files.clone(),
+            ..CheckpointEntry::new("sync", last_seen, new_watermark.clone())
+        };
+        if let Err(err) = caching::history::record(entry).await {
+            warn!("Warning: Unable to record checkpoint history: {err}");
+        }
+    }
+
     // return the updated timestamp for saving to memory
-    new_watermark
+    report
+}
+
+/// Sets the watermark the next sync continues from, i.e. to rewind and
+/// fetch logs again. Waits for a running sync of this instance and fails
+/// when another instance is syncing, so the cached and in-memory values are
+/// updated together. Returns the recorded history entry.
+#[instrument(name = "set_watermark")]
+pub async fn set_watermark(
+    data: Data<LogScraperState>,
+    watermark: String,
+    reason: &str,
+) -> Result<CheckpointEntry, String> {
+    let mut last_seen = data.last_seen.lock().await;
+
+    let redis_lock = if caching::uses_redis() {
+        match caching::acquire_lock(&data.redis, SYNC_LOCK_SOURCE).await? {
+            Some(lock) => Some(lock),
+            None => return Err("Another instance is syncing, try again later".to_owned()),
+        }
+    } else {
+        None
+    };
+
+    let result = async {
+        let previous = match caching::get_cached_val().await? {
+            cached if !cached.is_empty() => cached,
+            _ => last_seen.clone(),
+        };
+        caching::set_cached_val(watermark.clone()).await?;
+        *last_seen = watermark.clone();
+        info!("Set last_seen timestamp from {previous} to {watermark} ({reason})");
+
+        let entry = CheckpointEntry::new(reason, previous, watermark.clone());
+        if let Err(err) = caching::history::record(entry.clone()).await {
+            warn!("Warning: Unable to record checkpoint history: {err}");
+        }
+        Ok(entry)
+    }
+    .await;
+
+    if let Some(lock) = redis_lock {
+        if let Err(err) = lock.release(&data.redis).await {
+            warn!("Warning: {err}");
+        }
+    }
+    result
 }
 
 /// Fetches, prints and saves new logs from New Relic based on last_seen timestamp.
-async fn run_new_relic_sync(last_seen: String) -> String {
+/// Returns the new watermark along with what was written.
+async fn run_new_relic_sync(last_seen: String) -> SyncReport {
+    // don't fetch logs that can't be stored, the watermark stays put until there's room
+    if let Err(reason) = storage::quota::ensure_capacity().await {
+        warn!("Skipping sync due to storage pressure. {reason}");
+        return SyncReport::unchanged(last_seen, Some(format!("Storage pressure: {reason}")));
+    }
+
     let nr = NewRelic::new();
 
     // bail if there are no new logs to sync
-    let log_results = match nr.logs_since(&last_seen).await {
-        Ok(logs) => logs,
+    let (log_results, hit_limit) = match nr.logs_since(&last_seen).await {
+        Ok(fetched) => (fetched.logs, fetched.hit_limit),
         Err(err) => {
             warn!("There was an error fetching new relic logs since {last_seen} {err}");
             info!("Caching old timestamp to remote: {last_seen}");
             save_to_cache(last_seen.clone()).await;
-            return last_seen;
+            return SyncReport::unchanged(last_seen, Some(format!("Unable to fetch logs: {err}")));
         }
     };
 
@@ -92,7 +282,7 @@ async fn run_new_relic_sync(last_seen: String) -> String {
         // but make sure we cache the value to stay in sync
         info!("No logs found. Caching old timestamp to remote: {last_seen}");
         save_to_cache(last_seen.clone()).await;
-        return last_seen;
+        return SyncReport::unchanged(last_seen, None);
     }
 
     // print the logs to the console
@@ -100,32 +290,117 @@ async fn run_new_relic_sync(last_seen: String) -> String {
 
     let latest_log = nr.find_latest(&log_results);
     let watermark = nr.to_watermark(&latest_log);
+    let logs_fetched = log_results.len();
+
+    // group the logs by the partition folder they are stored under
+    let mut partitions: BTreeMap<String, Vec<NewRelicLogItem>> = BTreeMap::new();
+    for log in log_results {
+        partitions
+            .entry(storage::get_partition(&log))
+            .or_default()
+            .push(log);
+    }
 
-    // Save the logs to disk
-    let filename = storage::get_filename(latest_log.timestamp).await;
-    info!("Writing to file: {filename} . . .");
-    let data = log_results
-        .iter()
-        .map(|l| l.to_string())
-        .collect::<Vec<String>>();
-
-    let success = match storage::write_to_file(&filename, &data.join("\n")).await {
-        Ok(()) => {
-            info!("Successfully wrote logs to {filename} √");
-            let line_count = storage::total_lines(&filename).await.unwrap_or(0);
-            info!("Total lines in file: {line_count}");
-            true
+    // Save the logs to storage
+    let store = storage::global();
+    let mut partition_watermarks = read_partition_watermarks().await;
+    let had_partition_watermarks = !partition_watermarks.is_empty();
+    let mut failed = vec![];
+    // the oldest log (in milliseconds) that couldn't be written
+    let mut failed_since: Option<i64> = None;
+    let mut files = vec![];
+    for (partition, mut logs) in partitions {
+        // skip logs written to this partition by an earlier, partly failed sync
+        if let Some(written_until) = partition_watermarks.get(&partition) {
+            logs.retain(|log| log.timestamp.timestamp_millis() >= *written_until);
+            if logs.is_empty() {
+                continue;
+            }
         }
-        Err(err) => {
-            warn!("Warning: An error occurred saving logs to file: {err:?}");
-            false
+        let oldest = logs
+            .iter()
+            .map(|log| log.timestamp.timestamp_millis())
+            .min();
+
+        // hold the partition while picking the filename and writing to it
+        let _partition_lock = match storage::file_lock::acquire(&partition).await {
+            Ok(lock) => lock,
+            Err(err) => {
+                warn!("Warning: Unable to lock partition '{partition}': {err:?}");
+                failed.push(partition);
+                failed_since = [failed_since, oldest].into_iter().flatten().min();
+                continue;
+            }
+        };
+
+        let partition_latest = nr.find_latest(&logs);
+        let filename = store
+            .get_filename(&partition, partition_latest.timestamp)
+            .await;
+        info!("Writing to file: {filename} . . .");
+
+        match storage::write_logs(&filename, &logs).await {
+            Ok(()) => {
+                info!("Successfully wrote logs to {filename} √");
+                let line_count = store.total_lines(&filename).await.unwrap_or(0);
+                info!("Total lines in file: {line_count}");
+                files.push(filename);
+                let written_until = partition_latest.timestamp.timestamp_millis() + 1;
+                partition_watermarks.insert(partition, written_until);
+            }
+            Err(err) => {
+                warn!("Warning: An error occurred saving logs to file {filename}: {err:?}");
+                failed.push(filename);
+                failed_since = [failed_since, oldest].into_iter().flatten().min();
+            }
+        };
+    }
+
+    if let Some(failed_since) = failed_since {
+        // only advance up to the oldest log that wasn't written, keeping the
+        // watermarks of partitions written past it
+        partition_watermarks.retain(|_, written_until| *written_until > failed_since);
+        if let Err(err) = write_partition_watermarks(&partition_watermarks).await {
+            // without them the written partitions would get the logs again
+            warn!("Warning: Unable to save the partition watermarks: {err:?}");
+            return SyncReport {
+                logs_fetched,
+                hit_limit,
+                files,
+                ..SyncReport::unchanged(
+                    last_seen,
+                    Some(format!("Unable to save the partition watermarks: {err:?}")),
+                )
+            };
         }
-    };
+        let watermark = match last_seen.parse::<i64>() {
+            Ok(previous) if previous >= failed_since => last_seen.clone(),
+            _ => failed_since.to_string(),
+        };
+        return SyncReport {
+            logs_fetched,
+            hit_limit,
+            files,
+            watermark_before: last_seen,
+            watermark_after: watermark,
+            error: Some(format!("Unable to write logs to {}", failed.join(", "))),
+            ..Default::default()
+        };
+    }
 
-    if !success {
-        // revert to previous since write wasn't successful
-        return last_seen;
+    // every partition caught up with the watermark
+    if had_partition_watermarks {
+        if let Err(err) = write_partition_watermarks(&BTreeMap::new()).await {
+            warn!("Warning: Unable to clear the partition watermarks: {err:?}");
+        }
     }
 
-    watermark
+    SyncReport {
+        logs_fetched,
+        hit_limit,
+        files,
+        watermark_before: last_seen,
+        watermark_after: watermark,
+        ..Default::default()
+    }
 }
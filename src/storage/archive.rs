@@ -0,0 +1,229 @@
+//! # Archive Module
+//!
+//! Moves closed log files to an S3-compatible archive tier.
+//!
+//! ## Path
+//!
+//! storage/archive.rs
+//!
+//! # Description
+//!
+//! Uploads log files that will no longer be written to (rolled over or from a
+//! previous day) from `LOG_DIRECTORY` to the configured bucket and records
+//! where each one went in a manifest file. When `LS_ARCHIVE_DELETE_LOCAL` is
+//! enabled the local copy is removed once uploaded, and `restore` brings an
+//! archived file back to disk on demand.
+//!
+//! ## Notes
+//!
+//! Only the `files` storage backend is archived. The manifest is kept as
+//! `.archive_manifest.json` under `LOG_DIRECTORY`.
+//!
+//! Archived filenames are never reused for new logs (see `get_filename`) and
+//! an archived object is never overwritten by a different file.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use tokio::sync::Mutex;
+use tracing::{event, info, instrument, warn, Level};
+
+use crate::env_config::{
+    EnvConfig, LOG_DIRECTORY, LS_ARCHIVE_DELETE_LOCAL, LS_STORAGE_BACKEND, S3_PREFIX,
+};
+use crate::storage::s3::S3Client;
+use crate::storage::{self, MAX_LINES_PER_FILE};
+
+/// Filename of the manifest under `LOG_DIRECTORY`.
+const MANIFEST_NAME: &str = ".archive_manifest.json";
+
+/// Serializes manifest updates between the archive job and restores.
+static MANIFEST_LOCK: Mutex<()> = Mutex::const_new(());
+
+/// Where an archived file was uploaded to.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ArchiveEntry {
+    pub bucket: String,
+    pub key: String,
+    pub size: u64,
+    pub uploaded_at: DateTime<Utc>,
+}
+
+/// Record of every archived file keyed by filename.
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct ArchiveManifest {
+    pub files: BTreeMap<String, ArchiveEntry>,
+}
+
+fn get_log_path(filename: &str) -> PathBuf {
+    Path::new(&EnvConfig::global().get_val(LOG_DIRECTORY)).join(filename)
+}
+
+/// Reads the manifest from disk (empty if it doesn't exist yet).
+pub async fn read_manifest() -> ArchiveManifest {
+    match tokio::fs::read_to_string(get_log_path(MANIFEST_NAME)).await {
+        Ok(contents) => serde_json::from_str(&contents).unwrap_or_else(|err| {
+            event!(
+                Level::ERROR,
+                "Unable to parse the archive manifest: {err:?}"
+            );
+            ArchiveManifest::default()
+        }),
+        Err(_) => ArchiveManifest::default(),
+    }
+}
+
+/// Writes the manifest to disk atomically (via a temp file and rename).
+async fn write_manifest(manifest: &ArchiveManifest) -> tokio::io::Result<()> {
+    let path = get_log_path(MANIFEST_NAME);
+    let tmp_path = path.with_extension("json.tmp");
+    let contents = serde_json::to_string_pretty(manifest)?;
+    tokio::fs::write(&tmp_path, contents).await?;
+    tokio::fs::rename(&tmp_path, &path).await
+}
+
+/// Determines whether a file will no longer be written to, either because it
+/// rolled over or because it was last modified before the current day.
+pub(crate) async fn is_closed(filename: &str) -> bool {
+    let store = storage::global();
+    if store.total_lines(filename).await.unwrap_or(0) >= MAX_LINES_PER_FILE {
+        return true;
+    }
+
+    let start_of_day = Utc::now()
+        .date_naive()
+        .and_hms_opt(0, 0, 0)
+        .unwrap()
+        .and_utc();
+    match tokio::fs::metadata(get_log_path(filename))
+        .await
+        .and_then(|m| m.modified())
+    {
+        Ok(modified) => DateTime::<Utc>::from(modified) < start_of_day,
+        Err(_) => false,
+    }
+}
+
+/// Uploads closed log files to the archive bucket and records them in the
+/// manifest. Removes local copies that match their archived size when
+/// `LS_ARCHIVE_DELETE_LOCAL` is enabled.
+#[instrument(name = "run_archive")]
+pub async fn run_archive() -> Result<(), String> {
+    let env = EnvConfig::global();
+    if env.get_val(LS_STORAGE_BACKEND) != "files" {
+        warn!("Archiving is only supported by the files storage backend");
+        return Ok(());
+    }
+    let delete_local = env.get_val(LS_ARCHIVE_DELETE_LOCAL) == "true";
+    let prefix = env.get_val(S3_PREFIX);
+    let client = S3Client::new()?;
+
+    let _guard = MANIFEST_LOCK.lock().await;
+    let mut manifest = read_manifest().await;
+
+    for filename in storage::global().get_log_filenames().await {
+        if !is_closed(&filename).await {
+            continue;
+        }
+        // held through upload and removal so writers can't change the file
+        // between measuring, uploading and deleting it
+        let _lock = storage::file_lock::acquire(&filename)
+            .await
+            .map_err(|e| format!("Unable to lock {filename}: {e:?}"))?;
+        let size = match tokio::fs::metadata(get_log_path(&filename)).await {
+            Ok(m) => m.len(),
+            Err(_) => continue,
+        };
+
+        // upload unless the file was already archived, never overwriting an
+        // archived version that differs (its name isn't reused for new logs)
+        let archived_size = match manifest.files.get(&filename) {
+            Some(entry) if entry.size != size => {
+                warn!(
+                    "Not archiving {filename}, it differs from its archived copy at {}",
+                    entry.key
+                );
+                continue;
+            }
+            Some(entry) => entry.size,
+            None => {
+                let data = tokio::fs::read(get_log_path(&filename))
+                    .await
+                    .map_err(|e| format!("Unable to read {filename}: {e:?}"))?;
+                let uploaded_size = data.len() as u64;
+                let key = if prefix.is_empty() {
+                    filename.clone()
+                } else {
+                    format!("{}/{filename}", prefix.trim_end_matches('/'))
+                };
+
+                client.put_object(&key, data).await?;
+                info!("Archived {filename} to {}/{key}", client.bucket());
+                manifest.files.insert(
+                    filename.clone(),
+                    ArchiveEntry {
+                        bucket: client.bucket().to_owned(),
+                        key,
+                        size: uploaded_size,
+                        uploaded_at: Utc::now(),
+                    },
+                );
+                write_manifest(&manifest)
+                    .await
+                    .map_err(|e| format!("Unable to write the archive manifest: {e:?}"))?;
+                uploaded_size
+            }
+        };
+
+        if delete_local {
+            // only remove the exact bytes that are in the archive
+            match tokio::fs::metadata(get_log_path(&filename)).await {
+                Ok(m) if m.len() == archived_size => {}
+                Ok(_) => {
+                    warn!("Keeping local copy of {filename}, it changed since it was archived");
+                    continue;
+                }
+                Err(_) => continue,
+            }
+            match tokio::fs::remove_file(get_log_path(&filename)).await {
+                Ok(()) => info!("Removed local copy of archived file {filename}"),
+                Err(err) => warn!("Unable to remove local copy of {filename}: {err:?}"),
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Downloads an archived file back into `LOG_DIRECTORY`. Returns `Ok(false)`
+/// when the file isn't in the archive.
+#[instrument(name = "restore_archived_file")]
+pub async fn restore(filename: &str) -> Result<bool, String> {
+    let _guard = MANIFEST_LOCK.lock().await;
+    let manifest = read_manifest().await;
+    let Some(entry) = manifest.files.get(filename) else {
+        return Ok(false);
+    };
+
+    let client = S3Client::new()?;
+    if client.bucket() != entry.bucket {
+        return Err(format!(
+            "{filename} was archived to bucket {} but {} is configured",
+            entry.bucket,
+            client.bucket()
+        ));
+    }
+
+    let data = client.get_object(&entry.key).await?;
+    let path = get_log_path(filename);
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent)
+            .await
+            .map_err(|e| format!("Unable to create folder for {filename}: {e:?}"))?;
+    }
+    tokio::fs::write(path, data)
+        .await
+        .map_err(|e| format!("Unable to write {filename}: {e:?}"))?;
+    info!("Restored {filename} from the archive");
+    Ok(true)
+}
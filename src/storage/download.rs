@@ -0,0 +1,217 @@
+//! # Download Module
+//!
+//! Streams whole log files and bundles of files for downloading.
+//!
+//! ## Path
+//!
+//! storage/download.rs
+//!
+//! # Description
+//!
+//! Reads files page by page through the storage backend (so encrypted files
+//! are streamed decrypted) and sends the lines as chunks over a channel,
+//! keeping only one page in memory at a time. Lines can be limited to a time
+//! range and the output can be gzip-compressed on the fly.
+//!
+//! Bundles are tar archives with an entry per file holding the lines in the
+//! time range, where files without any such lines are left out.
+//!
+//! ## Notes
+//!
+//! Tar entries need their size up front, so each file of a bundle is read
+//! into memory before its entry is sent.
+
+use actix_web::web::Bytes;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use futures_util::Stream;
+use std::io::Write;
+use tokio::sync::mpsc;
+use tracing::{event, Level};
+
+use crate::storage::{self, READ_PAGE_SIZE};
+
+/// Number of chunks buffered before the reader waits on the client.
+const CHANNEL_CAPACITY: usize = 4;
+
+/// Block size of tar archives.
+const TAR_BLOCK_SIZE: usize = 512;
+
+/// Options for a download.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DownloadOptions {
+    pub from: Option<i64>,
+    pub to: Option<i64>,
+    pub gzip: bool,
+}
+
+impl DownloadOptions {
+    /// Determines whether the line falls into the time range. Lines that
+    /// can't be parsed are only kept when no range was given.
+    fn in_range(&self, line: &str) -> bool {
+        if self.from.is_none() && self.to.is_none() {
+            return true;
+        }
+        let Some(timestamp) = storage::line_timestamp(line) else {
+            return false;
+        };
+        !(self.from.is_some_and(|f| timestamp < f) || self.to.is_some_and(|t| timestamp > t))
+    }
+}
+
+type Chunk = Result<Bytes, std::io::Error>;
+
+/// Optionally gzip-compresses the output, handing out the compressed bytes
+/// produced so far after each write.
+struct Output {
+    encoder: Option<GzEncoder<Vec<u8>>>,
+}
+
+impl Output {
+    fn new(gzip: bool) -> Output {
+        Output {
+            encoder: gzip.then(|| GzEncoder::new(Vec::new(), Compression::default())),
+        }
+    }
+
+    fn write(&mut self, data: Vec<u8>) -> std::io::Result<Bytes> {
+        match self.encoder.as_mut() {
+            Some(encoder) => {
+                encoder.write_all(&data)?;
+                Ok(Bytes::from(std::mem::take(encoder.get_mut())))
+            }
+            None => Ok(Bytes::from(data)),
+        }
+    }
+
+    fn finish(self) -> std::io::Result<Bytes> {
+        match self.encoder {
+            Some(encoder) => Ok(Bytes::from(encoder.finish()?)),
+            None => Ok(Bytes::new()),
+        }
+    }
+}
+
+/// Reads one page of a file and returns the lines in the time range, each
+/// ending with a newline. Returns `None` after the last page.
+async fn read_page(
+    filename: &str,
+    page: u32,
+    options: &DownloadOptions,
+) -> std::io::Result<Option<Vec<u8>>> {
+    let lines = storage::global()
+        .get_lines_by_page(filename, page, READ_PAGE_SIZE)
+        .await?;
+    if lines.is_empty() {
+        return Ok(None);
+    }
+
+    let mut data: Vec<u8> = Vec::new();
+    for line in lines {
+        let line = line.trim_end_matches('\n');
+        if options.in_range(line) {
+            data.extend_from_slice(line.as_bytes());
+            data.push(b'\n');
+        }
+    }
+    Ok(Some(data))
+}
+
+/// Turns the receiving end of a channel into a stream of chunks.
+fn into_stream(rx: mpsc::Receiver<Chunk>) -> impl Stream<Item = Chunk> {
+    futures_util::stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|chunk| (chunk, rx))
+    })
+}
+
+/// Sends a chunk unless it's empty. Returns `false` when the client went away.
+async fn send(tx: &mpsc::Sender<Chunk>, chunk: std::io::Result<Bytes>) -> bool {
+    match chunk {
+        Ok(bytes) if bytes.is_empty() => true,
+        Ok(bytes) => tx.send(Ok(bytes)).await.is_ok(),
+        Err(err) => {
+            event!(Level::ERROR, "Unable to stream download: {err:?}");
+            let _ = tx.send(Err(err)).await;
+            false
+        }
+    }
+}
+
+/// Streams the lines of a file.
+pub fn stream_file(filename: String, options: DownloadOptions) -> impl Stream<Item = Chunk> {
+    let (tx, rx) = mpsc::channel::<Chunk>(CHANNEL_CAPACITY);
+    tokio::spawn(async move {
+        let mut output = Output::new(options.gzip);
+        let mut page = 1;
+        loop {
+            let chunk = match read_page(&filename, page, &options).await {
+                Ok(Some(data)) => output.write(data),
+                Ok(None) => break,
+                Err(err) => Err(err),
+            };
+            if !send(&tx, chunk).await {
+                return;
+            }
+            page += 1;
+        }
+        send(&tx, output.finish()).await;
+    });
+    into_stream(rx)
+}
+
+/// Builds the tar header and padded contents of a bundle entry.
+fn tar_entry(filename: &str, data: Vec<u8>) -> std::io::Result<Vec<u8>> {
+    let mut header = tar::Header::new_gnu();
+    header.set_path(filename)?;
+    header.set_size(data.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(chrono::Utc::now().timestamp() as u64);
+    header.set_cksum();
+
+    let padding = (TAR_BLOCK_SIZE - data.len() % TAR_BLOCK_SIZE) % TAR_BLOCK_SIZE;
+    let mut entry = header.as_bytes().to_vec();
+    entry.extend(data);
+    entry.extend(vec![0; padding]);
+    Ok(entry)
+}
+
+/// Streams a tar archive of the given files limited to the time range.
+pub fn stream_bundle(
+    filenames: Vec<String>,
+    options: DownloadOptions,
+) -> impl Stream<Item = Chunk> {
+    let (tx, rx) = mpsc::channel::<Chunk>(CHANNEL_CAPACITY);
+    tokio::spawn(async move {
+        let mut output = Output::new(options.gzip);
+        for filename in filenames {
+            let mut data: Vec<u8> = Vec::new();
+            let mut page = 1;
+            let result = loop {
+                match read_page(&filename, page, &options).await {
+                    Ok(Some(page_data)) => data.extend(page_data),
+                    Ok(None) => break Ok(()),
+                    Err(err) => break Err(err),
+                }
+                page += 1;
+            };
+            if let Err(err) = result {
+                send(&tx, Err(err)).await;
+                return;
+            }
+            if data.is_empty() {
+                continue;
+            }
+
+            let chunk = tar_entry(&filename, data).and_then(|entry| output.write(entry));
+            if !send(&tx, chunk).await {
+                return;
+            }
+        }
+
+        // a tar archive ends with two empty blocks
+        if send(&tx, output.write(vec![0; TAR_BLOCK_SIZE * 2])).await {
+            send(&tx, output.finish()).await;
+        }
+    });
+    into_stream(rx)
+}
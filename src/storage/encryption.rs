@@ -0,0 +1,278 @@
+//! # Encryption Module
+//!
+//! Encrypts log lines at rest with AES-256-GCM.
+//!
+//! ## Path
+//!
+//! storage/encryption.rs
+//!
+//! # Description
+//!
+//! When `LS_ENCRYPTION_ENABLED` is set, the storage backends encrypt every
+//! line they write and decrypt it again when reading, so the logs API,
+//! search index and exports keep working with plain JSON lines. Encrypted
+//! lines are stored as:
+//!
+//! ```text
+//! enc:v1:{key_id}:{base64(nonce + ciphertext)}
+//! ```
+//!
+//! Keys are configured as a keyring via `LS_ENCRYPTION_KEYS`
+//! (`id:base64key,...` with 32 byte keys, i.e. from `openssl rand -base64 32`)
+//! and new lines are encrypted with the key named by `LS_ENCRYPTION_KEY_ID`
+//! (or the first key when unset). Because every line records its key id,
+//! keys can be rotated by adding a new key, making it the active one and
+//! keeping the old ones around for reading, or running the `reencrypt`
+//! command to rewrite existing lines with the active key.
+//!
+//! ## Notes
+//!
+//! Lines without the `enc:v1:` prefix are returned as-is, so files written
+//! before encryption was enabled stay readable. The search index is built
+//! from decrypted lines and should be disabled when it must not hold plain
+//! text.
+
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use once_cell::sync::OnceCell;
+use std::collections::HashMap;
+use tracing::{event, Level};
+
+use crate::env_config::{
+    EnvConfig, LS_ENCRYPTION_ENABLED, LS_ENCRYPTION_KEYS, LS_ENCRYPTION_KEY_ID,
+};
+
+/// Prefix marking an encrypted line.
+const PREFIX: &str = "enc:v1:";
+
+/// Byte length of an AES-GCM nonce.
+const NONCE_LEN: usize = 12;
+
+static KEYRING: OnceCell<Keyring> = OnceCell::new();
+
+/// The configured keys by id along with the key new lines are encrypted with.
+pub struct Keyring {
+    active: Option<String>,
+    ciphers: HashMap<String, Aes256Gcm>,
+}
+
+impl std::fmt::Debug for Keyring {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut ids = self.ciphers.keys().collect::<Vec<&String>>();
+        ids.sort();
+        f.debug_struct("Keyring")
+            .field("active", &self.active)
+            .field("key_ids", &ids)
+            .finish()
+    }
+}
+
+impl Keyring {
+    /// Creates a new `Keyring` struct from the `LS_ENCRYPTION_*` configuration values.
+    fn from_config() -> Result<Keyring, String> {
+        let env = EnvConfig::global();
+        let mut ciphers: HashMap<String, Aes256Gcm> = HashMap::new();
+        let mut first_id: Option<String> = None;
+
+        for entry in env.get_val(LS_ENCRYPTION_KEYS).split(',').map(str::trim) {
+            if entry.is_empty() {
+                continue;
+            }
+            let (id, encoded) = entry.split_once(':').ok_or(format!(
+                "Encryption key '{entry}' must be given as id:base64key"
+            ))?;
+            if id.is_empty() || id.contains(':') {
+                return Err(format!("Invalid encryption key id '{id}'"));
+            }
+            let bytes = STANDARD
+                .decode(encoded)
+                .map_err(|e| format!("Encryption key '{id}' is not valid base64: {e:?}"))?;
+            if bytes.len() != 32 {
+                return Err(format!(
+                    "Encryption key '{id}' must be 32 bytes, got {}",
+                    bytes.len()
+                ));
+            }
+            ciphers.insert(
+                id.to_owned(),
+                Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&bytes)),
+            );
+            first_id.get_or_insert(id.to_owned());
+        }
+
+        let configured_id = env.get_val(LS_ENCRYPTION_KEY_ID);
+        let active = if configured_id.is_empty() {
+            first_id
+        } else if ciphers.contains_key(&configured_id) {
+            Some(configured_id)
+        } else {
+            return Err(format!(
+                "Active encryption key '{configured_id}' is not in the keyring"
+            ));
+        };
+
+        if env.get_val(LS_ENCRYPTION_ENABLED) == "true" && active.is_none() {
+            return Err("Encryption is enabled but no keys are configured".to_owned());
+        }
+        Ok(Keyring { active, ciphers })
+    }
+}
+
+/// Loads the keyring. Fails when encryption is enabled without a usable key.
+pub fn init() -> Result<(), String> {
+    let keyring = Keyring::from_config()?;
+    if EnvConfig::global().get_val(LS_ENCRYPTION_ENABLED) == "true" {
+        event!(Level::INFO, "Encrypting stored logs with {keyring:?}");
+    }
+    if KEYRING.set(keyring).is_err() {
+        event!(Level::WARN, "Encryption keyring was already initialized");
+    }
+    Ok(())
+}
+
+fn keyring() -> Option<&'static Keyring> {
+    KEYRING.get()
+}
+
+/// Determines whether new lines are encrypted.
+pub fn is_enabled() -> bool {
+    EnvConfig::global().get_val(LS_ENCRYPTION_ENABLED) == "true"
+}
+
+/// Determines whether a stored line is encrypted with a key other than the
+/// active one (or not encrypted at all while encryption is enabled).
+pub fn needs_reencrypt(line: &str) -> bool {
+    if !is_enabled() {
+        return false;
+    }
+    let active = keyring().and_then(|k| k.active.as_deref());
+    match line
+        .strip_prefix(PREFIX)
+        .and_then(|rest| rest.split_once(':'))
+    {
+        Some((key_id, _)) => Some(key_id) != active,
+        None => true,
+    }
+}
+
+/// Encrypts a line with the active key. Returns the line unchanged when
+/// encryption is disabled.
+pub fn encrypt_line(line: &str) -> tokio::io::Result<String> {
+    if !is_enabled() {
+        return Ok(line.to_owned());
+    }
+    let keyring = keyring().ok_or(tokio::io::Error::other(
+        "Encryption keyring is not initialized",
+    ))?;
+    let (key_id, cipher) = keyring
+        .active
+        .as_ref()
+        .and_then(|id| keyring.ciphers.get(id).map(|c| (id, c)))
+        .ok_or(tokio::io::Error::other("No active encryption key"))?;
+
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let ciphertext = cipher
+        .encrypt(&nonce, line.as_bytes())
+        .map_err(|e| tokio::io::Error::other(format!("Unable to encrypt line: {e:?}")))?;
+
+    let mut payload = nonce.to_vec();
+    payload.extend(ciphertext);
+    Ok(format!("{PREFIX}{key_id}:{}", STANDARD.encode(payload)))
+}
+
+/// Decrypts a stored line (keeping a trailing newline). Lines that aren't
+/// encrypted are returned unchanged.
+pub fn decrypt_line(line: &str) -> tokio::io::Result<String> {
+    let (content, newline) = match line.strip_suffix('\n') {
+        Some(content) => (content, "\n"),
+        None => (line, ""),
+    };
+    let Some(rest) = content.strip_prefix(PREFIX) else {
+        return Ok(line.to_owned());
+    };
+
+    let (key_id, encoded) = rest
+        .split_once(':')
+        .ok_or(tokio::io::Error::other("Malformed encrypted line"))?;
+    let cipher = keyring()
+        .and_then(|k| k.ciphers.get(key_id))
+        .ok_or(tokio::io::Error::other(format!(
+            "Unknown encryption key '{key_id}'"
+        )))?;
+    let payload = STANDARD
+        .decode(encoded)
+        .map_err(|e| tokio::io::Error::other(format!("Malformed encrypted line: {e:?}")))?;
+    if payload.len() < NONCE_LEN {
+        return Err(tokio::io::Error::other("Malformed encrypted line"));
+    }
+
+    let (nonce, ciphertext) = payload.split_at(NONCE_LEN);
+    let plaintext = cipher
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .map_err(|_| {
+            tokio::io::Error::other(format!("Unable to decrypt line with key '{key_id}'"))
+        })?;
+    let plaintext = String::from_utf8(plaintext).map_err(tokio::io::Error::other)?;
+    Ok(format!("{plaintext}{newline}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::env_config::init_test_config;
+
+    fn init_keyring() {
+        init_test_config();
+        init().unwrap();
+    }
+
+    #[test]
+    fn encrypted_lines_decrypt_to_the_original() {
+        init_keyring();
+        let encrypted = encrypt_line("{\"message\":\"hello\"}").unwrap();
+        assert!(encrypted.starts_with("enc:v1:prod:"));
+        assert!(!encrypted.contains("hello"));
+        assert_eq!(decrypt_line(&encrypted).unwrap(), "{\"message\":\"hello\"}");
+        assert_eq!(
+            decrypt_line(&format!("{encrypted}\n")).unwrap(),
+            "{\"message\":\"hello\"}\n"
+        );
+    }
+
+    #[test]
+    fn encrypting_uses_a_new_nonce_every_time() {
+        init_keyring();
+        assert_ne!(encrypt_line("same").unwrap(), encrypt_line("same").unwrap());
+    }
+
+    #[test]
+    fn plain_lines_are_read_as_is() {
+        init_keyring();
+        assert_eq!(
+            decrypt_line("{\"message\":\"plain\"}\n").unwrap(),
+            "{\"message\":\"plain\"}\n"
+        );
+    }
+
+    #[test]
+    fn decrypting_fails_for_unknown_keys_and_altered_lines() {
+        init_keyring();
+        let encrypted = encrypt_line("hello").unwrap();
+        assert!(decrypt_line(&encrypted.replacen(":prod:", ":gone:", 1)).is_err());
+        assert!(decrypt_line(&encrypted.replacen(":prod:", ":old:", 1)).is_err());
+        assert!(decrypt_line("enc:v1:prod:bm9wZQ==").is_err());
+    }
+
+    #[test]
+    fn needs_reencrypt_for_lines_without_the_active_key() {
+        init_keyring();
+        assert!(!needs_reencrypt(&encrypt_line("hello").unwrap()));
+        let old = encrypt_line("hello")
+            .unwrap()
+            .replacen(":prod:", ":old:", 1);
+        assert!(needs_reencrypt(&old));
+        assert!(needs_reencrypt("{\"message\":\"plain\"}"));
+    }
+}
@@ -0,0 +1,83 @@
+//! # File Lock Module
+//!
+//! Serializes writers of the same file across threads and processes.
+//!
+//! ## Path
+//!
+//! storage/file_lock.rs
+//!
+//! # Description
+//!
+//! Takes an exclusive advisory lock (`flock` / `LockFileEx`) on a lock file
+//! kept for each locked name under `.locks` in `LOG_DIRECTORY`, so replicas
+//! sharing the same volume (as well as the cron thread and `/logs/sync`
+//! within one process) never interleave writes. The lock is held until the
+//! returned guard is dropped.
+//!
+//! ## Notes
+//!
+//! Lock files are kept separate from the log files since a file may not
+//! exist yet (or get renamed) while it's locked. They're left in place after
+//! unlocking and reused by the next writer.
+
+use fs4::fs_std::FileExt;
+use std::fs::{File, OpenOptions};
+use std::path::{Path, PathBuf};
+use tracing::{trace, warn};
+
+use crate::env_config::{EnvConfig, LOG_DIRECTORY};
+
+/// Folder name of the lock files under `LOG_DIRECTORY`.
+const LOCK_DIR: &str = ".locks";
+
+/// Name of the lock file for the root partition.
+const ROOT_LOCK_NAME: &str = "_root";
+
+/// Holds an exclusive lock until dropped.
+#[derive(Debug)]
+pub struct FileLock {
+    name: String,
+    file: File,
+}
+
+impl Drop for FileLock {
+    fn drop(&mut self) {
+        if let Err(err) = FileExt::unlock(&self.file) {
+            warn!("Unable to unlock {}: {err:?}", self.name);
+        }
+        trace!("Released lock on {}", self.name);
+    }
+}
+
+fn get_lock_path(name: &str) -> PathBuf {
+    let name = if name.is_empty() {
+        ROOT_LOCK_NAME
+    } else {
+        name
+    };
+    Path::new(&EnvConfig::global().get_val(LOG_DIRECTORY))
+        .join(LOCK_DIR)
+        .join(format!("{name}.lock"))
+}
+
+/// Waits for an exclusive lock on the given name, i.e. a filename or a
+/// partition path.
+pub async fn acquire(name: &str) -> tokio::io::Result<FileLock> {
+    let path = get_lock_path(name);
+    let name = name.to_owned();
+    tokio::task::spawn_blocking(move || {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&path)?;
+        file.lock_exclusive()?;
+        trace!("Acquired lock on {name}");
+        Ok(FileLock { name, file })
+    })
+    .await
+    .map_err(tokio::io::Error::other)?
+}
@@ -0,0 +1,489 @@
+//! # File Store Module
+//!
+//! Handles saving and reading files to and from disk.
+//!
+//! ## Path
+//!
+//! storage/file_store.rs
+//!
+//! # Description
+//!
+//! Allows reading and writing log records as JSON lines to files under the
+//! folder configured via the `LOG_DIRECTORY` environment setting. Files that
+//! were gzip-compressed (see the `quota` module) are read transparently.
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use flate2::read::GzDecoder;
+use std::cmp;
+use std::collections::HashMap;
+use std::io::SeekFrom;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+use tokio::fs::{File, OpenOptions};
+use tokio::io::{
+    AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncSeekExt, AsyncWriteExt, BufReader,
+};
+use tracing::{event, instrument, Level};
+
+use crate::env_config::{EnvConfig, LOG_DIRECTORY};
+use crate::new_relic::types::NewRelicLogItem;
+use crate::storage::{self, encryption, FileInfo, LogStore};
+
+/// New line character to check when reading files
+const LF: u8 = b'\n';
+
+/// Extension of gzip-compressed log files.
+pub(crate) const GZIP_EXTENSION: &str = ".gz";
+
+/// Number of bytes read at a time when reading files backward.
+const BACKWARD_CHUNK_SIZE: u64 = 8192;
+
+fn get_log_dir() -> String {
+    EnvConfig::global().get_val(LOG_DIRECTORY)
+}
+
+fn get_log_path(filename: &str) -> PathBuf {
+    Path::new(&get_log_dir()).join(filename)
+}
+
+/// Opens a buffered reader over the lines of a file, decompressing
+/// gzip-compressed files into memory first.
+async fn open_reader(filename: &str) -> tokio::io::Result<Box<dyn AsyncBufRead + Unpin + Send>> {
+    let path = get_log_path(filename);
+    if filename.ends_with(GZIP_EXTENSION) {
+        let compressed = tokio::fs::read(path).await?;
+        let data = tokio::task::spawn_blocking(move || {
+            let mut data = Vec::new();
+            std::io::Read::read_to_end(&mut GzDecoder::new(compressed.as_slice()), &mut data)
+                .map(|_| data)
+        })
+        .await
+        .map_err(tokio::io::Error::other)??;
+        return Ok(Box::new(std::io::Cursor::new(data)));
+    }
+    Ok(Box::new(BufReader::new(File::open(path).await?)))
+}
+
+/// Creates the directory set via LOG_DIRECTORY configuration if it doesn't exist.
+pub async fn ensure_log_directory() -> tokio::io::Result<()> {
+    let dir_name = get_log_dir();
+    let p = Path::new(&dir_name);
+    if !p.exists() {
+        tokio::fs::create_dir_all(p).await?;
+    }
+    Ok(())
+}
+
+/// Appends data to the log file with the given filename.
+async fn append_to_file(filename: &str, data: &str) -> tokio::io::Result<()> {
+    let filepath = get_log_path(filename);
+    let mut file = OpenOptions::new().append(true).open(filepath).await?;
+    file.write_all(data.as_bytes()).await?;
+    Ok(())
+}
+
+/// Writes data to a new log file with the given filename, creating any
+/// partition folders it is nested under.
+async fn write_to_new_file(filename: &str, data: &str) -> tokio::io::Result<()> {
+    let filepath = get_log_path(filename);
+    if let Some(parent) = filepath.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    let mut file = File::create(filepath).await?;
+    file.write_all(data.as_bytes()).await?;
+    Ok(())
+}
+
+/// Writes a string to a file. Appends if file already exists.
+pub async fn write_to_file(filename: &str, data: &str) -> tokio::io::Result<()> {
+    ensure_log_directory()
+        .await
+        .expect("Unable to create log directory");
+
+    if !get_log_path(filename).exists() {
+        write_to_new_file(filename, data).await
+    } else {
+        let data_with_newline = format!("\n{data}");
+        append_to_file(filename, &data_with_newline).await
+    }
+}
+
+/// Recursively collects the relative paths of the files under the given folder.
+fn collect_filenames(dir: &Path, relative_dir: &str, result: &mut Vec<String>) {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) => {
+            event!(Level::ERROR, "Unable to read directory {dir:?}: {err:?}");
+            return;
+        }
+    };
+
+    for dir_entry in entries.flatten() {
+        let name = dir_entry.file_name();
+        let entry_name = name.to_string_lossy();
+        if entry_name.starts_with('.') {
+            continue; // skip hidden files and folders (manifests, indices etc.)
+        }
+
+        let relative_name = if relative_dir.is_empty() {
+            entry_name.into_owned()
+        } else {
+            format!("{relative_dir}/{entry_name}")
+        };
+
+        match dir_entry.metadata() {
+            Ok(m) if m.is_dir() => collect_filenames(&dir_entry.path(), &relative_name, result),
+            Ok(_) => result.push(relative_name),
+            Err(_) => continue,
+        }
+    }
+}
+
+/// Storage backend that keeps logs as JSON lines in files, either flat or
+/// nested in partition folders.
+#[derive(Debug)]
+pub struct FileStore {
+    /// File metadata by filename along with the modified time and size it
+    /// was read at, so files are only scanned again after they changed.
+    info_cache: Mutex<HashMap<String, (SystemTime, u64, FileInfo)>>,
+}
+
+impl FileStore {
+    /// Creates a new `FileStore` struct and sets up the logging storage area.
+    pub async fn new() -> tokio::io::Result<FileStore> {
+        ensure_log_directory().await?;
+        Ok(FileStore {
+            info_cache: Mutex::new(HashMap::new()),
+        })
+    }
+
+    fn cached_info(&self, filename: &str, modified: SystemTime, size: u64) -> Option<FileInfo> {
+        let cache = self
+            .info_cache
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        cache
+            .get(filename)
+            .filter(|(m, s, _)| *m == modified && *s == size)
+            .map(|(_, _, info)| info.clone())
+    }
+}
+
+#[async_trait]
+impl LogStore for FileStore {
+    /// Reads and returns the list of currently residing log files on the filesystem
+    /// under the folder configured via the `LOG_DIRECTORY` environment setting.
+    /// Files in nested (partition) folders are listed by their relative path.
+    #[instrument(name = "get_log_filenames")]
+    async fn get_log_filenames(&self) -> Vec<String> {
+        let mut result: Vec<String> = Vec::new();
+
+        let log_location = get_log_dir();
+        let p = Path::new(&log_location);
+        if !p.exists() {
+            event!(Level::ERROR, "Error: folder does not exist: {log_location}");
+            return result;
+        }
+
+        collect_filenames(p, "", &mut result);
+        result
+    }
+
+    async fn has_file(&self, filename: &str) -> bool {
+        get_log_path(filename).exists()
+    }
+
+    async fn total_lines(&self, filename: &str) -> tokio::io::Result<usize> {
+        let mut f = open_reader(filename).await?;
+
+        let mut count = 0;
+        let z: usize = 0;
+        let mut line: Vec<u8> = Vec::new();
+        while match f.read_until(LF, &mut line).await {
+            Ok(n) => n > z,
+            Err(e) => return Err(e),
+        } {
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// Reads file with given filename from disk and returns the contents in lines.
+    /// Allows pagination through lines in the file via page and lines_per_page parameters.
+    /// Note: use `total_lines` fn for obtaining the total number of lines in a file.
+    async fn get_lines_by_page(
+        &self,
+        filename: &str,
+        page: u32,
+        lines_per_page: u32,
+    ) -> tokio::io::Result<Vec<String>> {
+        let mut reader = open_reader(filename).await?;
+
+        let mut cursor = 0;
+        let mut results: Vec<String> = Vec::new();
+
+        // ensure params have sane values
+        let normalized_page: u32 = cmp::max(1, page);
+        let normalized_max_lines: u32 = cmp::max(1, lines_per_page);
+
+        loop {
+            cursor += 1;
+
+            for _ in 0..normalized_max_lines {
+                // read up to the end of the line
+                let mut buffer = Vec::new();
+                reader.read_until(LF, &mut buffer).await?;
+
+                if !buffer.is_empty() && cursor == normalized_page {
+                    let line = std::str::from_utf8(&buffer).unwrap_or("");
+                    results.push(encryption::decrypt_line(line)?);
+                }
+            }
+
+            if cursor >= normalized_page {
+                break;
+            }
+        }
+        Ok(results)
+    }
+
+    /// Reads the metadata from the filesystem and scans the lines for the
+    /// count and record timestamp range (cached until the file changes).
+    async fn file_info(&self, filename: &str) -> tokio::io::Result<FileInfo> {
+        let metadata = tokio::fs::metadata(get_log_path(filename)).await?;
+        let modified = metadata.modified()?;
+        if let Some(info) = self.cached_info(filename, modified, metadata.len()) {
+            return Ok(info);
+        }
+
+        let lines = self.get_all_lines(filename).await?;
+        let timestamps = lines.iter().filter_map(|l| storage::line_timestamp(l));
+        let info = FileInfo {
+            name: filename.to_owned(),
+            size: metadata.len(),
+            lines: Some(lines.len()),
+            created: metadata.created().ok().map(DateTime::<Utc>::from),
+            modified: Some(DateTime::<Utc>::from(modified)),
+            min_timestamp: timestamps.clone().min(),
+            max_timestamp: timestamps.max(),
+            ..FileInfo::default()
+        };
+
+        self.info_cache
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .insert(
+                filename.to_owned(),
+                (modified, metadata.len(), info.clone()),
+            );
+        Ok(info)
+    }
+
+    /// Reads the file backward in chunks from the end, so only the requested
+    /// lines (and the skipped ones after them) are read from disk.
+    async fn get_last_lines(
+        &self,
+        filename: &str,
+        skip: usize,
+        count: usize,
+    ) -> tokio::io::Result<Vec<String>> {
+        // compressed files can't be read backward, so they're read whole
+        if filename.ends_with(GZIP_EXTENSION) {
+            let lines = self.get_all_lines(filename).await?;
+            return Ok(lines.into_iter().rev().skip(skip).take(count).collect());
+        }
+
+        let mut f = File::open(get_log_path(filename)).await?;
+        let mut position = f.metadata().await?.len();
+
+        // bytes read so far that haven't been split into lines yet
+        let mut pending: Vec<u8> = Vec::new();
+        let mut results: Vec<String> = Vec::new();
+        let mut lines_from_end: usize = 0;
+
+        while results.len() < count {
+            // a trailing LF belongs to the line itself, so look for the one before it
+            let search_end = pending.len().saturating_sub(1);
+            let line = match pending[..search_end].iter().rposition(|b| *b == LF) {
+                Some(i) => pending.split_off(i + 1),
+                None if position == 0 => {
+                    if pending.is_empty() {
+                        break;
+                    }
+                    std::mem::take(&mut pending)
+                }
+                None => {
+                    let read_size = cmp::min(BACKWARD_CHUNK_SIZE, position);
+                    position -= read_size;
+                    f.seek(SeekFrom::Start(position)).await?;
+                    let mut chunk = vec![0; read_size as usize];
+                    f.read_exact(&mut chunk).await?;
+                    chunk.extend(pending);
+                    pending = chunk;
+                    continue;
+                }
+            };
+
+            if lines_from_end >= skip {
+                let line = std::str::from_utf8(&line).unwrap_or("");
+                results.push(encryption::decrypt_line(line)?);
+            }
+            lines_from_end += 1;
+        }
+        Ok(results)
+    }
+
+    async fn write_logs(&self, filename: &str, logs: &[NewRelicLogItem]) -> tokio::io::Result<()> {
+        let data = logs
+            .iter()
+            .map(|l| encryption::encrypt_line(&l.to_string()))
+            .collect::<tokio::io::Result<Vec<String>>>()?;
+        write_to_file(filename, &data.join("\n")).await
+    }
+
+    #[instrument(name = "delete_file")]
+    async fn delete_file(&self, filename: &str) -> tokio::io::Result<()> {
+        if !self.has_file(filename).await {
+            event!(Level::ERROR, "Unable to read file: {filename}");
+            return Ok(());
+        }
+
+        tokio::fs::remove_file(get_log_path(filename)).await
+    }
+
+    /// Re-encrypts the lines of a file into a temp file that then replaces it.
+    #[instrument(name = "reencrypt_file")]
+    async fn reencrypt(&self, filename: &str) -> tokio::io::Result<usize> {
+        if filename.ends_with(GZIP_EXTENSION) {
+            return Err(tokio::io::Error::other(
+                "Compressed files can't be re-encrypted",
+            ));
+        }
+        let path = get_log_path(filename);
+        let contents = tokio::fs::read_to_string(&path).await?;
+
+        let mut rewritten = 0;
+        let mut lines: Vec<String> = Vec::new();
+        for line in contents.split('\n') {
+            if encryption::needs_reencrypt(line) && !line.is_empty() {
+                lines.push(encryption::encrypt_line(&encryption::decrypt_line(line)?)?);
+                rewritten += 1;
+            } else {
+                lines.push(line.to_owned());
+            }
+        }
+        if rewritten == 0 {
+            return Ok(0);
+        }
+
+        let tmp_path = path.with_file_name(format!(
+            ".{}.tmp",
+            path.file_name().unwrap_or_default().to_string_lossy()
+        ));
+        tokio::fs::write(&tmp_path, lines.join("\n")).await?;
+        tokio::fs::rename(&tmp_path, &path).await?;
+        Ok(rewritten)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::env_config::init_test_config;
+
+    /// Writes the contents to a file of the test `LOG_DIRECTORY`, returning a store reading it.
+    async fn store_with_file(filename: &str, contents: &str) -> FileStore {
+        init_test_config();
+        let path = get_log_path(filename);
+        tokio::fs::create_dir_all(path.parent().unwrap())
+            .await
+            .unwrap();
+        tokio::fs::write(path, contents).await.unwrap();
+        FileStore::new().await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn get_last_lines_reads_newest_first() {
+        let filename = "file_store_tests/newest_first.log";
+        let store = store_with_file(filename, "1\n2\n3\n4\n5\n").await;
+
+        assert_eq!(
+            store.get_last_lines(filename, 0, 2).await.unwrap(),
+            ["5\n", "4\n"]
+        );
+        assert_eq!(
+            store.get_last_lines(filename, 1, 2).await.unwrap(),
+            ["4\n", "3\n"]
+        );
+        assert_eq!(
+            store.get_last_lines(filename, 0, 10).await.unwrap(),
+            ["5\n", "4\n", "3\n", "2\n", "1\n"]
+        );
+        assert!(store
+            .get_last_lines(filename, 5, 2)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn get_last_lines_reads_a_last_line_without_newline() {
+        let filename = "file_store_tests/no_trailing_newline.log";
+        let store = store_with_file(filename, "1\n2\n3").await;
+
+        assert_eq!(
+            store.get_last_lines(filename, 0, 2).await.unwrap(),
+            ["3", "2\n"]
+        );
+    }
+
+    #[tokio::test]
+    async fn get_last_lines_reads_lines_across_chunks() {
+        let filename = "file_store_tests/across_chunks.log";
+        let contents = (1..=3000)
+            .map(|i| format!("line {i:05}\n"))
+            .collect::<String>();
+        assert!(contents.len() as u64 > 2 * BACKWARD_CHUNK_SIZE);
+        let store = store_with_file(filename, &contents).await;
+
+        let lines = store.get_last_lines(filename, 1500, 3).await.unwrap();
+        assert_eq!(lines, ["line 01500\n", "line 01499\n", "line 01498\n"]);
+        let all = store.get_last_lines(filename, 0, 3000).await.unwrap();
+        assert_eq!(all.len(), 3000);
+        assert_eq!(all.last().map(String::as_str), Some("line 00001\n"));
+    }
+
+    #[tokio::test]
+    async fn get_last_lines_reads_compressed_files() {
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        std::io::Write::write_all(&mut encoder, b"1\n2\n3\n").unwrap();
+        let compressed = encoder.finish().unwrap();
+        init_test_config();
+        let filename = "file_store_tests/compressed.log.gz";
+        let path = get_log_path(filename);
+        tokio::fs::create_dir_all(path.parent().unwrap())
+            .await
+            .unwrap();
+        tokio::fs::write(path, compressed).await.unwrap();
+        let store = FileStore::new().await.unwrap();
+
+        assert_eq!(
+            store.get_last_lines(filename, 1, 5).await.unwrap(),
+            ["2\n", "1\n"]
+        );
+    }
+
+    #[tokio::test]
+    async fn get_last_lines_reads_empty_files() {
+        let filename = "file_store_tests/empty.log";
+        let store = store_with_file(filename, "").await;
+
+        assert!(store
+            .get_last_lines(filename, 0, 5)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+}
@@ -0,0 +1,663 @@
+//! # Hash Chain Module
+//!
+//! Keeps a tamper-evident record of every batch of lines written to a file.
+//!
+//! ## Path
+//!
+//! storage/hash_chain.rs
+//!
+//! # Description
+//!
+//! Each batch appended to a file extends a rolling SHA-256 hash chain:
+//!
+//! ```text
+//! hash(n) = sha256(hash(n - 1) + line_1 + "\n" + ... + line_k + "\n")
+//! ```
+//!
+//! The chain entries are kept as JSON lines next to a manifest holding the
+//! head of the chain, which is signed with an HMAC-SHA256 of the configured
+//! `LS_HASH_CHAIN_KEY`. Verifying a file recomputes the chain from its
+//! current lines, so any altered, removed or added line is detected.
+//!
+//! When a batch can't be added to the chain after its lines were written, the
+//! file is marked unverifiable instead, which verifying it reports.
+//!
+//! Deleting a file (via the API or quota eviction) replaces its chain with a
+//! signed tombstone recording the head of the chain and when it was deleted,
+//! so a file can't disappear without a trace while its name can be reused.
+//!
+//! ## Notes
+//!
+//! Chains are stored under `.hash_chains` in `LOG_DIRECTORY`, mirroring the
+//! (possibly nested) filename, for every storage backend.
+
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use tokio::io::AsyncWriteExt;
+use tracing::instrument;
+
+use crate::env_config::{EnvConfig, LOG_DIRECTORY, LS_HASH_CHAIN_ENABLED, LS_HASH_CHAIN_KEY};
+use crate::storage;
+
+/// Folder name of the chains under `LOG_DIRECTORY`.
+const CHAIN_DIR: &str = ".hash_chains";
+
+/// The previous hash of the first batch in a chain.
+const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// Reported when signatures can't be checked.
+const NO_KEY_ERROR: &str = "No LS_HASH_CHAIN_KEY is configured to check the signature";
+
+/// A batch of lines appended to a file.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ChainEntry {
+    pub batch: u64,
+    pub first_line: u64,
+    pub line_count: u64,
+    pub prev_hash: String,
+    pub hash: String,
+    pub written_at: DateTime<Utc>,
+}
+
+/// The signed head of a file's chain.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ChainManifest {
+    pub filename: String,
+    pub batches: u64,
+    pub lines: u64,
+    pub head_hash: String,
+    pub signature: Option<String>,
+}
+
+/// The signed record of a file deleted along with its chain.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Tombstone {
+    pub filename: String,
+    pub batches: u64,
+    pub lines: u64,
+    pub head_hash: String,
+    pub deleted_at: DateTime<Utc>,
+    pub signature: Option<String>,
+}
+
+/// The outcome of verifying a file against its chain.
+#[derive(Serialize, Debug)]
+pub struct VerifyReport {
+    pub filename: String,
+    pub ok: bool,
+    pub batches: u64,
+    pub lines: u64,
+    pub signature_valid: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<DateTime<Utc>>,
+    pub errors: Vec<String>,
+}
+
+fn get_chain_path(filename: &str, ext: &str) -> PathBuf {
+    Path::new(&EnvConfig::global().get_val(LOG_DIRECTORY))
+        .join(CHAIN_DIR)
+        .join(format!("{filename}.{ext}"))
+}
+
+/// Computes the hash of a batch of lines chained to the previous hash.
+fn batch_hash(prev_hash: &str, lines: &[String]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(prev_hash.as_bytes());
+    for line in lines {
+        hasher.update(line.trim_end_matches('\n').as_bytes());
+        hasher.update(b"\n");
+    }
+    hex::encode(hasher.finalize())
+}
+
+/// Signs the head of a chain. Returns `None` when no key is configured.
+fn sign(filename: &str, lines: u64, head_hash: &str) -> Option<String> {
+    let key = EnvConfig::global().get_val(LS_HASH_CHAIN_KEY);
+    if key.is_empty() {
+        return None;
+    }
+    let mut mac = Hmac::<Sha256>::new_from_slice(key.as_bytes()).ok()?;
+    mac.update(format!("{filename}:{lines}:{head_hash}").as_bytes());
+    Some(hex::encode(mac.finalize().into_bytes()))
+}
+
+/// Signs a tombstone, covering when the file was deleted.
+fn sign_tombstone(tombstone: &Tombstone) -> Option<String> {
+    let deleted = format!(
+        "{}:deleted:{}",
+        tombstone.head_hash,
+        tombstone.deleted_at.timestamp_millis()
+    );
+    sign(&tombstone.filename, tombstone.lines, &deleted)
+}
+
+/// Determines whether hash chains are maintained.
+pub fn is_enabled() -> bool {
+    EnvConfig::global().get_val(LS_HASH_CHAIN_ENABLED) == "true"
+}
+
+/// Reads all entries of a file's chain.
+async fn read_entries(filename: &str) -> tokio::io::Result<Vec<ChainEntry>> {
+    let contents = tokio::fs::read_to_string(get_chain_path(filename, "chain")).await?;
+    contents
+        .lines()
+        .filter(|l| !l.is_empty())
+        .map(|l| serde_json::from_str::<ChainEntry>(l).map_err(tokio::io::Error::other))
+        .collect()
+}
+
+/// Reads the tombstones of a file, oldest first.
+async fn read_tombstones(filename: &str) -> tokio::io::Result<Vec<Tombstone>> {
+    let contents = tokio::fs::read_to_string(get_chain_path(filename, "tombstones")).await?;
+    contents
+        .lines()
+        .filter(|l| !l.is_empty())
+        .map(|l| serde_json::from_str::<Tombstone>(l).map_err(tokio::io::Error::other))
+        .collect()
+}
+
+/// Reads the manifest of a file's chain.
+async fn read_manifest(filename: &str) -> tokio::io::Result<ChainManifest> {
+    let contents = tokio::fs::read_to_string(get_chain_path(filename, "manifest.json")).await?;
+    serde_json::from_str(&contents).map_err(tokio::io::Error::other)
+}
+
+/// Extends the chain of a file with a batch of lines that was just written
+/// (starting at line number `first_line`) and re-signs the manifest.
+#[instrument(name = "append_hash_chain", skip(lines))]
+pub async fn append(filename: &str, first_line: u64, lines: &[String]) -> tokio::io::Result<()> {
+    let chain_path = get_chain_path(filename, "chain");
+    if let Some(parent) = chain_path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+
+    let (batch, prev_hash) = match read_manifest(filename).await {
+        Ok(m) => (m.batches + 1, m.head_hash),
+        Err(_) => (1, GENESIS_HASH.to_owned()),
+    };
+    let entry = ChainEntry {
+        batch,
+        first_line,
+        line_count: lines.len() as u64,
+        hash: batch_hash(&prev_hash, lines),
+        prev_hash,
+        written_at: Utc::now(),
+    };
+
+    let mut chain_file = tokio::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&chain_path)
+        .await?;
+    chain_file
+        .write_all(format!("{}\n", serde_json::to_string(&entry)?).as_bytes())
+        .await?;
+
+    // write the manifest atomically (via a temp file and rename)
+    let lines_total = first_line - 1 + entry.line_count;
+    let manifest = ChainManifest {
+        filename: filename.to_owned(),
+        batches: batch,
+        lines: lines_total,
+        signature: sign(filename, lines_total, &entry.hash),
+        head_hash: entry.hash,
+    };
+    let manifest_path = get_chain_path(filename, "manifest.json");
+    let tmp_path = get_chain_path(filename, "manifest.json.tmp");
+    tokio::fs::write(&tmp_path, serde_json::to_string_pretty(&manifest)?).await?;
+    tokio::fs::rename(&tmp_path, &manifest_path).await
+}
+
+/// Records that a batch of lines written to a file is missing from its chain,
+/// so verifying the file fails with the reason.
+pub async fn mark_unverifiable(
+    filename: &str,
+    first_line: u64,
+    line_count: usize,
+    reason: &str,
+) -> tokio::io::Result<()> {
+    let marker_path = get_chain_path(filename, "unverifiable");
+    if let Some(parent) = marker_path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    let last_line = first_line + line_count as u64 - 1;
+    let mut marker_file = tokio::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&marker_path)
+        .await?;
+    marker_file
+        .write_all(
+            format!(
+                "{}: lines {first_line}-{last_line} are not in the hash chain ({reason})\n",
+                Utc::now().to_rfc3339()
+            )
+            .as_bytes(),
+        )
+        .await
+}
+
+/// Replaces the chain of a deleted file with a signed tombstone (when chains
+/// are maintained or the file had one), so the deletion can be verified.
+pub async fn tombstone(filename: &str) -> tokio::io::Result<()> {
+    let manifest = read_manifest(filename).await.ok();
+    if manifest.is_none() && !is_enabled() {
+        return Ok(());
+    }
+    let (batches, lines, head_hash) = manifest.map_or((0, 0, GENESIS_HASH.to_owned()), |m| {
+        (m.batches, m.lines, m.head_hash)
+    });
+    let mut tombstone = Tombstone {
+        filename: filename.to_owned(),
+        batches,
+        lines,
+        head_hash,
+        deleted_at: Utc::now(),
+        signature: None,
+    };
+    tombstone.signature = sign_tombstone(&tombstone);
+
+    let tombstone_path = get_chain_path(filename, "tombstones");
+    if let Some(parent) = tombstone_path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    let mut tombstone_file = tokio::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&tombstone_path)
+        .await?;
+    tombstone_file
+        .write_all(format!("{}\n", serde_json::to_string(&tombstone)?).as_bytes())
+        .await?;
+    tombstone_file.sync_all().await?;
+
+    // a new file under the same name starts a chain of its own
+    for ext in ["chain", "manifest.json", "unverifiable"] {
+        match tokio::fs::remove_file(get_chain_path(filename, ext)).await {
+            Err(err) if err.kind() != tokio::io::ErrorKind::NotFound => return Err(err),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Determines whether a tombstone is kept for the file.
+pub fn has_tombstone(filename: &str) -> bool {
+    get_chain_path(filename, "tombstones").exists()
+}
+
+/// Lists the files that were deleted (and weren't written again since).
+pub async fn deleted_files() -> Vec<String> {
+    let dir = Path::new(&EnvConfig::global().get_val(LOG_DIRECTORY)).join(CHAIN_DIR);
+    let mut filenames = Vec::new();
+    collect_tombstones(&dir, &dir, &mut filenames);
+    filenames.retain(|f| !has_chain(f));
+    filenames.sort();
+    filenames
+}
+
+/// Recursively collects the filenames of the tombstones under the folder.
+fn collect_tombstones(root: &Path, dir: &Path, filenames: &mut Vec<String>) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    for path in entries.flatten().map(|entry| entry.path()) {
+        if path.is_dir() {
+            collect_tombstones(root, &path, filenames);
+        } else if let Some(name) = path
+            .strip_prefix(root)
+            .ok()
+            .and_then(|p| p.to_str())
+            .and_then(|p| p.strip_suffix(".tombstones"))
+        {
+            filenames.push(name.to_owned());
+        }
+    }
+}
+
+/// Determines whether a chain is kept for the file.
+pub fn has_chain(filename: &str) -> bool {
+    get_chain_path(filename, "chain").exists()
+}
+
+/// Moves the chain of a renamed (i.e. compressed) file. Lines are hashed by
+/// content, so the chain stays valid under the new name; the manifest is
+/// re-signed for it. Fails rather than overwriting the chain of another file.
+pub async fn rename(filename: &str, new_filename: &str) -> tokio::io::Result<()> {
+    let chain_path = get_chain_path(filename, "chain");
+    if !chain_path.exists() {
+        return Ok(());
+    }
+    if has_chain(new_filename) {
+        return Err(tokio::io::Error::new(
+            tokio::io::ErrorKind::AlreadyExists,
+            format!("A hash chain already exists for {new_filename}"),
+        ));
+    }
+    tokio::fs::rename(&chain_path, get_chain_path(new_filename, "chain")).await?;
+    let marker_path = get_chain_path(filename, "unverifiable");
+    if marker_path.exists() {
+        tokio::fs::rename(&marker_path, get_chain_path(new_filename, "unverifiable")).await?;
+    }
+
+    let mut manifest = read_manifest(filename).await?;
+    manifest.filename = new_filename.to_owned();
+    manifest.signature = sign(new_filename, manifest.lines, &manifest.head_hash);
+    tokio::fs::write(
+        get_chain_path(new_filename, "manifest.json"),
+        serde_json::to_string_pretty(&manifest)?,
+    )
+    .await?;
+    tokio::fs::remove_file(get_chain_path(filename, "manifest.json")).await
+}
+
+/// Recomputes the chain of a file from its stored lines and compares it
+/// with the recorded chain and signed manifest.
+#[instrument(name = "verify_hash_chain")]
+pub async fn verify(filename: &str) -> VerifyReport {
+    let mut report = VerifyReport {
+        filename: filename.to_owned(),
+        ok: false,
+        batches: 0,
+        lines: 0,
+        signature_valid: None,
+        deleted_at: None,
+        errors: Vec::new(),
+    };
+
+    // batches that couldn't be added to the chain when they were written
+    if let Ok(marker) = tokio::fs::read_to_string(get_chain_path(filename, "unverifiable")).await {
+        report
+            .errors
+            .extend(marker.lines().filter(|l| !l.is_empty()).map(str::to_owned));
+    }
+
+    let (entries, manifest) = match (read_entries(filename).await, read_manifest(filename).await) {
+        (Ok(e), Ok(m)) => (e, m),
+        _ if has_tombstone(filename) => return verify_tombstones(filename, report).await,
+        _ => {
+            report
+                .errors
+                .push("No hash chain found for file".to_owned());
+            return report;
+        }
+    };
+    let lines = match storage::global().get_all_lines(filename).await {
+        Ok(lines) => lines,
+        Err(err) => {
+            report.errors.push(format!("Unable to read file: {err:?}"));
+            return report;
+        }
+    };
+    report.batches = entries.len() as u64;
+    report.lines = lines.len() as u64;
+
+    // recompute every batch of the chain from the stored lines
+    let mut prev_hash = GENESIS_HASH.to_owned();
+    let mut next_line: u64 = 1;
+    for entry in &entries {
+        if entry.first_line != next_line || entry.prev_hash != prev_hash {
+            report
+                .errors
+                .push(format!("Batch {} is out of sequence", entry.batch));
+        }
+        let start = (entry.first_line.saturating_sub(1)) as usize;
+        let end = start + entry.line_count as usize;
+        if end > lines.len() {
+            report.errors.push(format!(
+                "Batch {} expects lines {}-{} but the file has {} lines",
+                entry.batch,
+                entry.first_line,
+                end,
+                lines.len()
+            ));
+            break;
+        }
+        if batch_hash(&entry.prev_hash, &lines[start..end]) != entry.hash {
+            report.errors.push(format!(
+                "Batch {} (lines {}-{}) was altered",
+                entry.batch, entry.first_line, end
+            ));
+        }
+        prev_hash = entry.hash.clone();
+        next_line = end as u64 + 1;
+    }
+
+    if next_line - 1 != lines.len() as u64 {
+        report.errors.push(format!(
+            "File has {} lines but the chain covers {}",
+            lines.len(),
+            next_line - 1
+        ));
+    }
+    if manifest.head_hash != prev_hash || manifest.batches != report.batches {
+        report
+            .errors
+            .push("Manifest does not match the chain".to_owned());
+    }
+
+    // an unsigned manifest doesn't pass, nor does one that can't be checked
+    match sign(filename, manifest.lines, &manifest.head_hash) {
+        Some(expected) => {
+            let valid = manifest.signature.as_deref() == Some(expected.as_str());
+            report.signature_valid = Some(valid);
+            if manifest.signature.is_none() {
+                report.errors.push("Manifest is not signed".to_owned());
+            } else if !valid {
+                report
+                    .errors
+                    .push("Manifest signature is invalid".to_owned());
+            }
+        }
+        None => report.errors.push(NO_KEY_ERROR.to_owned()),
+    }
+
+    report.ok = report.errors.is_empty();
+    report
+}
+
+/// Reports the last deletion of a file without a chain, checking the
+/// signatures of its tombstones. The file must be gone for them to pass.
+async fn verify_tombstones(filename: &str, mut report: VerifyReport) -> VerifyReport {
+    let tombstones = match read_tombstones(filename).await {
+        Ok(tombstones) => tombstones,
+        Err(err) => {
+            report
+                .errors
+                .push(format!("Unable to read tombstones: {err:?}"));
+            return report;
+        }
+    };
+    let Some(last) = tombstones.last() else {
+        report
+            .errors
+            .push("No hash chain found for file".to_owned());
+        return report;
+    };
+    report.batches = last.batches;
+    report.lines = last.lines;
+    report.deleted_at = Some(last.deleted_at);
+
+    if storage::global().has_file(filename).await {
+        report
+            .errors
+            .push("File was written after it was deleted without a hash chain".to_owned());
+    }
+    for tombstone in &tombstones {
+        let Some(expected) = sign_tombstone(tombstone) else {
+            report.errors.push(NO_KEY_ERROR.to_owned());
+            break;
+        };
+        let valid = tombstone.signature.as_deref() == Some(expected.as_str());
+        if !valid {
+            report.errors.push(format!(
+                "Tombstone of the deletion at {} is unsigned or has an invalid signature",
+                tombstone.deleted_at.to_rfc3339()
+            ));
+        }
+        report.signature_valid = Some(report.signature_valid.unwrap_or(true) && valid);
+    }
+
+    report.ok = report.errors.is_empty();
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::env_config::init_test_config;
+    use crate::new_relic::types::NewRelicLogItem;
+    use serde_json::Map;
+
+    fn lines(values: &[&str]) -> Vec<String> {
+        values.iter().map(|v| v.to_string()).collect()
+    }
+
+    fn log_item(message: &str) -> NewRelicLogItem {
+        NewRelicLogItem {
+            logger_name: "api".to_owned(),
+            request_id: "r1".to_owned(),
+            logtype: "info".to_owned(),
+            message: message.to_owned(),
+            message_id: "m1".to_owned(),
+            project: "billing".to_owned(),
+            timestamp: Utc::now(),
+            attributes: Map::new(),
+        }
+    }
+
+    #[test]
+    fn batch_hash_is_chained_to_the_previous_hash() {
+        let batch = lines(&["a", "b"]);
+        let first = batch_hash(GENESIS_HASH, &batch);
+        assert_eq!(first, batch_hash(GENESIS_HASH, &batch));
+        assert_eq!(first.len(), 64);
+        assert_ne!(first, batch_hash(&first, &batch));
+    }
+
+    #[test]
+    fn batch_hash_ignores_trailing_newlines_only() {
+        let hash = batch_hash(GENESIS_HASH, &lines(&["a", "b"]));
+        assert_eq!(hash, batch_hash(GENESIS_HASH, &lines(&["a\n", "b\n"])));
+        assert_ne!(hash, batch_hash(GENESIS_HASH, &lines(&["ab"])));
+        assert_ne!(hash, batch_hash(GENESIS_HASH, &lines(&["b", "a"])));
+    }
+
+    #[tokio::test]
+    async fn verify_passes_for_written_files() {
+        init_test_config();
+        storage::init().await.unwrap();
+        let filename = "hash_chain_tests/written.log";
+        storage::write_logs(filename, &[log_item("one"), log_item("two")])
+            .await
+            .unwrap();
+        storage::write_logs(filename, &[log_item("three")])
+            .await
+            .unwrap();
+
+        let report = verify(filename).await;
+        assert!(report.ok, "{:?}", report.errors);
+        assert_eq!((report.batches, report.lines), (2, 3));
+    }
+
+    #[tokio::test]
+    async fn verify_detects_added_lines() {
+        init_test_config();
+        storage::init().await.unwrap();
+        let filename = "hash_chain_tests/added.log";
+        storage::write_logs(filename, &[log_item("one")])
+            .await
+            .unwrap();
+
+        let path = Path::new(&EnvConfig::global().get_val(LOG_DIRECTORY)).join(filename);
+        let mut file = tokio::fs::OpenOptions::new()
+            .append(true)
+            .open(path)
+            .await
+            .unwrap();
+        file.write_all(b"\n{\"message\":\"forged\"}").await.unwrap();
+
+        let report = verify(filename).await;
+        assert!(!report.ok);
+        assert!(
+            report.errors.iter().any(|e| e.contains("chain covers 1")),
+            "{:?}",
+            report.errors
+        );
+    }
+
+    #[tokio::test]
+    async fn verify_reports_deleted_files() {
+        init_test_config();
+        storage::init().await.unwrap();
+        let filename = "hash_chain_tests/deleted.log";
+        storage::write_logs(filename, &[log_item("one"), log_item("two")])
+            .await
+            .unwrap();
+        storage::delete_file(filename).await.unwrap();
+
+        let report = verify(filename).await;
+        assert!(report.ok, "{:?}", report.errors);
+        assert!(report.deleted_at.is_some());
+        assert_eq!(report.lines, 2);
+        assert!(deleted_files().await.contains(&filename.to_owned()));
+    }
+
+    #[tokio::test]
+    async fn failed_appends_keep_the_lines_and_fail_verification() {
+        init_test_config();
+        storage::init().await.unwrap();
+        let filename = "hash_chain_tests/unchained.log";
+        // a folder in place of the chain makes appending to it fail
+        tokio::fs::create_dir_all(get_chain_path(filename, "chain"))
+            .await
+            .unwrap();
+
+        storage::write_logs(filename, &[log_item("one"), log_item("two")])
+            .await
+            .unwrap();
+        assert_eq!(storage::global().total_lines(filename).await.unwrap(), 2);
+
+        let report = verify(filename).await;
+        assert!(!report.ok);
+        assert!(
+            report
+                .errors
+                .iter()
+                .any(|e| e.contains("lines 1-2 are not in the hash chain")),
+            "{:?}",
+            report.errors
+        );
+    }
+
+    #[tokio::test]
+    async fn verify_fails_for_unsigned_manifests() {
+        init_test_config();
+        storage::init().await.unwrap();
+        let filename = "hash_chain_tests/unsigned.log";
+        storage::write_logs(filename, &[log_item("one")])
+            .await
+            .unwrap();
+
+        let mut manifest = read_manifest(filename).await.unwrap();
+        manifest.signature = None;
+        tokio::fs::write(
+            get_chain_path(filename, "manifest.json"),
+            serde_json::to_string_pretty(&manifest).unwrap(),
+        )
+        .await
+        .unwrap();
+
+        let report = verify(filename).await;
+        assert!(!report.ok);
+        assert_eq!(report.signature_valid, Some(false));
+        assert!(
+            report.errors.iter().any(|e| e == "Manifest is not signed"),
+            "{:?}",
+            report.errors
+        );
+    }
+}
@@ -0,0 +1,507 @@
+//! # Storage Module
+//!
+//! Handles saving and reading log records to and from the configured backend.
+//!
+//! ## Path
+//!
+//! storage/mod.rs
+//!
+//! # Description
+//!
+//! Defines the `LogStore` trait that the API and scraper modules use for
+//! listing, reading, writing and deleting logs. The backend is selected via
+//! the `LS_STORAGE_BACKEND` configuration setting:
+//!
+//! - `files`: (default) JSON lines written to flat files under `LOG_DIRECTORY`
+//! - `sqlite`: records written to an embedded SQLite database
+//!
+//! Files can be partitioned into nested folders by fields of each record via
+//! the `LOG_PATH_TEMPLATE` configuration setting (see `get_partition`).
+//!
+//! Writes and deletes hold an advisory lock on the file (see the `file_lock`
+//! module) so writers sharing the same volume never interleave.
+//!
+//! Writes are guarded by the storage quota and disk-pressure policy (see the
+//! `quota` module).
+//!
+//! Both backends encrypt lines at rest when `LS_ENCRYPTION_ENABLED` is set
+//! (see the `encryption` module) and return them decrypted.
+//!
+//! Records written through `write_logs` are also added to the file's hash
+//! chain (see the `hash_chain` module) and the full-text search index (see
+//! the `search_index` module).
+//!
+//! ## Notes
+//!
+//! Defines a `STORE` instance to be set only once on app start (see `init`).
+
+pub mod archive;
+pub mod download;
+pub mod encryption;
+pub mod file_lock;
+mod file_store;
+pub mod hash_chain;
+pub mod parquet_export;
+pub mod quota;
+mod s3;
+pub mod search_index;
+mod sqlite_store;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use once_cell::sync::OnceCell;
+use serde::Serialize;
+use std::fmt::Debug;
+use tracing::{event, warn, Level};
+
+use crate::env_config::{
+    EnvConfig, LOG_FILE_EXTENSION, LOG_FILE_PREFIX, LOG_PATH_TEMPLATE, LS_STORAGE_BACKEND,
+};
+use crate::new_relic::types::NewRelicLogItem;
+use file_store::GZIP_EXTENSION;
+
+pub use file_store::FileStore;
+pub(crate) use sqlite_store::get_db_path;
+pub use sqlite_store::SqliteStore;
+
+/// The cell to init and hold the storage backend instance (only writable once).
+static STORE: OnceCell<Box<dyn LogStore>> = OnceCell::new();
+
+/// Max number of lines (records) a log file can hold before rolling over.
+pub(crate) const MAX_LINES_PER_FILE: usize = 1000;
+
+/// Number of lines read at a time when reading whole files.
+const READ_PAGE_SIZE: u32 = 1000;
+
+/// Metadata of a stored log file. Values a backend can't provide (or that
+/// are unknown for archived files no longer held locally) are left empty.
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct FileInfo {
+    pub name: String,
+    pub size: u64,
+    pub lines: Option<usize>,
+    pub created: Option<DateTime<Utc>>,
+    pub modified: Option<DateTime<Utc>>,
+    pub min_timestamp: Option<i64>,
+    pub max_timestamp: Option<i64>,
+    pub compressed: bool,
+    pub archived: bool,
+    pub local: bool,
+    pub rollover: u32,
+}
+
+/// Operations every storage backend must provide. Logs are grouped under
+/// "filenames" regardless of the backend so the API can keep listing, paging
+/// and deleting them the same way.
+#[async_trait]
+pub trait LogStore: Send + Sync + Debug {
+    /// Returns the list of log filenames currently held by the backend.
+    async fn get_log_filenames(&self) -> Vec<String>;
+
+    /// Determines whether the file with the given filename exists or not.
+    async fn has_file(&self, filename: &str) -> bool;
+
+    /// Reads the total lines (records) of a file.
+    async fn total_lines(&self, filename: &str) -> tokio::io::Result<usize>;
+
+    /// Reads the lines of a file one page at a time.
+    async fn get_lines_by_page(
+        &self,
+        filename: &str,
+        page: u32,
+        lines_per_page: u32,
+    ) -> tokio::io::Result<Vec<String>>;
+
+    /// Reads the size, line count, times and record timestamp range of a file.
+    async fn file_info(&self, filename: &str) -> tokio::io::Result<FileInfo>;
+
+    /// Reads up to `count` lines of a file backward from the end, newest
+    /// first, after skipping the last `skip` lines.
+    async fn get_last_lines(
+        &self,
+        filename: &str,
+        skip: usize,
+        count: usize,
+    ) -> tokio::io::Result<Vec<String>>;
+
+    /// Writes the log records under the given filename. Appends if the file already exists.
+    async fn write_logs(&self, filename: &str, logs: &[NewRelicLogItem]) -> tokio::io::Result<()>;
+
+    /// Deletes the file and all of its lines.
+    async fn delete_file(&self, filename: &str) -> tokio::io::Result<()>;
+
+    /// Rewrites the lines of a file that aren't encrypted with the active
+    /// encryption key. Returns the number of rewritten lines.
+    async fn reencrypt(&self, filename: &str) -> tokio::io::Result<usize>;
+
+    /// Reads all lines of a file.
+    async fn get_all_lines(&self, filename: &str) -> tokio::io::Result<Vec<String>> {
+        let mut results: Vec<String> = Vec::new();
+        let mut page = 1;
+        loop {
+            let lines = self
+                .get_lines_by_page(filename, page, READ_PAGE_SIZE)
+                .await?;
+            if lines.is_empty() {
+                break;
+            }
+            results.extend(lines);
+            page += 1;
+        }
+        Ok(results)
+    }
+
+    /// Determines whether the given filename should be written to or the name
+    /// should be rolled over to another filename.
+    async fn should_rollover(&self, filename: &str) -> bool {
+        self.has_file(filename).await && {
+            let num_lines = self.total_lines(filename).await.unwrap_or(0);
+            num_lines >= MAX_LINES_PER_FILE
+        }
+    }
+
+    /// Generates a string to use as a filename. For not appending to log files and just creating mutliple
+    /// log files under the same date.
+    /// Uses the given partition, timestamp and configured prefix and extension values to construct resulting
+    /// filename (nested under the partition path when one is given, see `get_partition`).
+    /// If the resulting filename is already in use the name will used based on the `should_rollover`
+    /// policy and can be appended with an underscore and number if filename rollover is needed.
+    async fn get_filename(&self, partition: &str, timestamp: DateTime<Utc>) -> String {
+        let env = EnvConfig::global();
+        let ext = env.get_val(LOG_FILE_EXTENSION);
+        let prefix = env.get_val(LOG_FILE_PREFIX);
+        let name = format!("{}_{}", prefix, timestamp.format("%Y-%m-%d"));
+        let base_name = if partition.is_empty() {
+            name
+        } else {
+            format!("{partition}/{name}")
+        };
+
+        let mut proposed_name = format!("{base_name}.{ext}");
+        let mut incrementor = 0;
+
+        // allow using existing filename based on rollover policy else increment with number
+        // (the names of compressed and archived files count as used, so they're never overwritten)
+        let archived = archive::read_manifest().await;
+        loop {
+            let compressed_name = format!("{proposed_name}{GZIP_EXTENSION}");
+            let rollover = self.should_rollover(&proposed_name).await
+                || self.has_file(&compressed_name).await
+                || archived.files.contains_key(&proposed_name)
+                || archived.files.contains_key(&compressed_name);
+            if !rollover {
+                break;
+            }
+            incrementor += 1;
+            proposed_name = format!("{base_name}_{incrementor}.{ext}");
+        }
+        proposed_name
+    }
+}
+
+/// Replaces characters that aren't safe in a path segment and guards against
+/// empty or relative (`.`/`..`) segments.
+fn to_path_segment(value: &str) -> String {
+    let cleaned = value
+        .chars()
+        .map(|c| match c {
+            'A'..='Z' | 'a'..='z' | '0'..='9' | '-' | '_' | '.' => c,
+            _ => '_',
+        })
+        .collect::<String>();
+    let trimmed = cleaned.trim_start_matches('.');
+    if trimmed.is_empty() {
+        "unknown".to_owned()
+    } else {
+        trimmed.to_owned()
+    }
+}
+
+/// Renders the `LOG_PATH_TEMPLATE` configuration for the given log record,
+/// giving the (relative) folder the record should be stored under. Supports
+/// the `{project}`, `{logtype}`, `{logger_name}`, `{date}`, `{year}`,
+/// `{month}` and `{day}` placeholders. Returns an empty string for the flat
+/// (default) layout.
+pub fn get_partition(log: &NewRelicLogItem) -> String {
+    let template = EnvConfig::global().get_val(LOG_PATH_TEMPLATE);
+    template
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+        .map(|segment| {
+            let rendered = segment
+                .replace("{project}", &log.project)
+                .replace("{logtype}", &log.logtype)
+                .replace("{logger_name}", &log.logger_name)
+                .replace("{date}", &log.timestamp.format("%Y-%m-%d").to_string())
+                .replace("{year}", &log.timestamp.format("%Y").to_string())
+                .replace("{month}", &log.timestamp.format("%m").to_string())
+                .replace("{day}", &log.timestamp.format("%d").to_string());
+            to_path_segment(&rendered)
+        })
+        .collect::<Vec<String>>()
+        .join("/")
+}
+
+/// Validates a filename requested via the API, which may be a nested path
+/// (i.e. `project/logtype/app_2023-01-01.log`). Returns `None` for absolute
+/// paths and paths with empty, hidden or relative (`.`/`..`) segments so
+/// requests can't traverse outside of the storage area.
+pub fn sanitize_filename(id: &str) -> Option<String> {
+    if id.is_empty() || id.contains('\\') {
+        return None;
+    }
+    let valid = id
+        .split('/')
+        .all(|segment| !segment.is_empty() && !segment.starts_with('.'));
+    valid.then(|| id.to_owned())
+}
+
+/// Creates the storage backend set via the `LS_STORAGE_BACKEND` configuration.
+pub async fn init() -> tokio::io::Result<()> {
+    encryption::init().map_err(|e| tokio::io::Error::new(tokio::io::ErrorKind::InvalidInput, e))?;
+
+    let backend = EnvConfig::global().get_val(LS_STORAGE_BACKEND);
+    let store: Box<dyn LogStore> = match backend.as_str() {
+        "files" => Box::new(FileStore::new().await?),
+        "sqlite" => Box::new(SqliteStore::new()?),
+        other => {
+            return Err(tokio::io::Error::new(
+                tokio::io::ErrorKind::InvalidInput,
+                format!("Unknown storage backend '{other}'"),
+            ))
+        }
+    };
+
+    event!(Level::INFO, "Using {backend} storage backend");
+    if STORE.set(store).is_err() {
+        event!(Level::WARN, "Storage backend was already initialized");
+    }
+
+    // search is an add-on, so don't fail startup when the index can't be opened
+    if let Err(err) = search_index::init() {
+        event!(Level::ERROR, "Unable to open the search index: {err}");
+    }
+    Ok(())
+}
+
+/// Get the current storage backend instance.
+pub fn global() -> &'static dyn LogStore {
+    STORE
+        .get()
+        .expect("Unable to resolve storage backend instance!")
+        .as_ref()
+}
+
+/// Writes the log records via the storage backend, extends the hash chain of
+/// the file and adds the records to the search index.
+pub async fn write_logs(filename: &str, logs: &[NewRelicLogItem]) -> tokio::io::Result<()> {
+    let _lock = file_lock::acquire(filename).await?;
+    let store = global();
+    let first_line = if store.has_file(filename).await {
+        store.total_lines(filename).await? + 1
+    } else {
+        1
+    };
+
+    store.write_logs(filename, logs).await?;
+    let lines = logs.iter().map(|l| l.to_string()).collect::<Vec<String>>();
+
+    // the lines are stored, failing the write now would fetch them again
+    if hash_chain::is_enabled() {
+        if let Err(err) = hash_chain::append(filename, first_line as u64, &lines).await {
+            event!(
+                Level::ERROR,
+                "Unable to extend the hash chain of {filename}: {err:?}"
+            );
+            let reason = format!("{err:?}");
+            let marked =
+                hash_chain::mark_unverifiable(filename, first_line as u64, lines.len(), &reason);
+            if let Err(err) = marked.await {
+                event!(
+                    Level::ERROR,
+                    "Unable to mark {filename} as unverifiable: {err:?}"
+                );
+            }
+        }
+    }
+
+    let name = filename.to_owned();
+    let indexed =
+        search_index::run(move |index| index.index_lines(&name, first_line as u64, &lines));
+    if let Some(Err(err)) = indexed.await {
+        warn!("Warning: An error occurred indexing logs of {filename}: {err}");
+    }
+    Ok(())
+}
+
+/// Deletes the file via the storage backend and removes its lines from the
+/// search index, replacing its hash chain with a tombstone.
+pub async fn delete_file(filename: &str) -> tokio::io::Result<()> {
+    let _lock = file_lock::acquire(filename).await?;
+    global().delete_file(filename).await?;
+    hash_chain::tombstone(filename).await?;
+
+    let name = filename.to_owned();
+    if let Some(Err(err)) = search_index::run(move |index| index.remove_file(&name)).await {
+        warn!("Warning: An error occurred removing {filename} from the search index: {err}");
+    }
+    Ok(())
+}
+
+/// Reads the timestamp (milliseconds) of a stored JSON line.
+pub(crate) fn line_timestamp(line: &str) -> Option<i64> {
+    serde_json::from_str::<serde_json::Map<String, serde_json::Value>>(line.trim_end())
+        .ok()?
+        .get("timestamp")?
+        .as_i64()
+}
+
+/// Parses the rollover sequence from a filename, i.e. `3` for
+/// `app_2023-01-01_3.log` and `0` for `app_2023-01-01.log`.
+fn rollover_sequence(filename: &str) -> u32 {
+    let basename = filename.rsplit('/').next().unwrap_or(filename);
+    let stem = basename.split('.').next().unwrap_or(basename);
+    stem.rsplit_once('_')
+        .and_then(|(_, sequence)| sequence.parse::<u32>().ok())
+        .unwrap_or(0)
+}
+
+/// Returns the metadata of every file held by the backend along with the
+/// archived files that are no longer held locally.
+pub async fn get_file_infos() -> Vec<FileInfo> {
+    let store = global();
+    let manifest = archive::read_manifest().await;
+    let mut infos: Vec<FileInfo> = Vec::new();
+
+    for filename in store.get_log_filenames().await {
+        match store.file_info(&filename).await {
+            Ok(info) => infos.push(FileInfo {
+                archived: manifest.files.contains_key(&filename),
+                local: true,
+                ..info
+            }),
+            Err(err) => warn!("Unable to read the metadata of {filename}: {err:?}"),
+        }
+    }
+
+    for (filename, entry) in manifest.files {
+        if infos.iter().any(|i| i.name == filename) {
+            continue;
+        }
+        infos.push(FileInfo {
+            name: filename,
+            size: entry.size,
+            modified: Some(entry.uploaded_at),
+            archived: true,
+            ..FileInfo::default()
+        });
+    }
+
+    for info in infos.iter_mut() {
+        info.compressed = info.name.ends_with(".gz");
+        info.rollover = rollover_sequence(&info.name);
+    }
+    infos
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::env_config::init_test_config;
+    use serde_json::Map;
+
+    fn log_item(project: &str, logtype: &str, timestamp: &str) -> NewRelicLogItem {
+        NewRelicLogItem {
+            logger_name: "api".to_owned(),
+            request_id: "r1".to_owned(),
+            logtype: logtype.to_owned(),
+            message: "hello".to_owned(),
+            message_id: "m1".to_owned(),
+            project: project.to_owned(),
+            timestamp: timestamp.parse().unwrap(),
+            attributes: Map::new(),
+        }
+    }
+
+    #[test]
+    fn sanitize_filename_accepts_nested_files() {
+        assert_eq!(
+            sanitize_filename("app_2023-01-01.log").as_deref(),
+            Some("app_2023-01-01.log")
+        );
+        assert_eq!(
+            sanitize_filename("web/error/app_2023-01-01.log").as_deref(),
+            Some("web/error/app_2023-01-01.log")
+        );
+    }
+
+    #[test]
+    fn sanitize_filename_rejects_paths_outside_storage() {
+        for id in [
+            "",
+            "/etc/passwd",
+            "../secrets",
+            "web/../../x.log",
+            "./app.log",
+            "web//app.log",
+            "web/",
+            "a\\b.log",
+        ] {
+            assert_eq!(sanitize_filename(id), None, "{id}");
+        }
+    }
+
+    #[test]
+    fn sanitize_filename_rejects_hidden_files() {
+        assert_eq!(sanitize_filename(".hash_chains/app.log.chain"), None);
+        assert_eq!(sanitize_filename("web/.archive_manifest.json"), None);
+    }
+
+    #[test]
+    fn to_path_segment_replaces_unsafe_characters() {
+        assert_eq!(to_path_segment("my-project_1.2"), "my-project_1.2");
+        assert_eq!(to_path_segment("my project/logs"), "my_project_logs");
+        assert_eq!(to_path_segment("../etc"), "_etc");
+    }
+
+    #[test]
+    fn to_path_segment_never_gives_empty_or_relative_segments() {
+        assert_eq!(to_path_segment(""), "unknown");
+        assert_eq!(to_path_segment("."), "unknown");
+        assert_eq!(to_path_segment(".."), "unknown");
+        assert_eq!(to_path_segment(".hidden"), "hidden");
+    }
+
+    #[test]
+    fn get_partition_renders_the_template() {
+        init_test_config();
+        let log = log_item("billing", "error", "2023-01-02T03:04:05Z");
+        assert_eq!(get_partition(&log), "billing/error/2023-01-02");
+    }
+
+    #[test]
+    fn get_partition_sanitizes_record_fields() {
+        init_test_config();
+        let log = log_item("../../etc", "", "2023-01-02T03:04:05Z");
+        assert_eq!(get_partition(&log), "_.._etc/unknown/2023-01-02");
+        assert_eq!(
+            sanitize_filename(&get_partition(&log)).as_deref(),
+            Some("_.._etc/unknown/2023-01-02")
+        );
+    }
+
+    #[test]
+    fn rollover_sequence_parses_the_suffix() {
+        assert_eq!(rollover_sequence("app_2023-01-01.log"), 0);
+        assert_eq!(rollover_sequence("app_2023-01-01_3.log"), 3);
+        assert_eq!(rollover_sequence("app_2023-01-01_12.log.gz"), 12);
+        assert_eq!(rollover_sequence("web_1/error/app_2023-01-01_2.log"), 2);
+    }
+
+    #[test]
+    fn rollover_sequence_ignores_other_underscores() {
+        assert_eq!(rollover_sequence("my_app_2023-01-01.log"), 0);
+        assert_eq!(rollover_sequence("web_7/app_2023-01-01.log"), 0);
+        assert_eq!(rollover_sequence("app"), 0);
+    }
+}
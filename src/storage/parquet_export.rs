@@ -0,0 +1,208 @@
+//! # Parquet Export Module
+//!
+//! Converts stored log records into Parquet for analytics tools.
+//!
+//! ## Path
+//!
+//! storage/parquet_export.rs
+//!
+//! # Description
+//!
+//! Reads the JSON lines of one or more stored files, optionally keeping only
+//! the records within a time range, and writes them out as a single Parquet
+//! file. The schema is made up of the `NewRelicLogItem` fields plus a column
+//! for every other attribute found on the records, with the column type
+//! inferred from the values seen (integer, float, boolean or string).
+
+use arrow_array::{
+    ArrayRef, BooleanArray, Float64Array, Int64Array, RecordBatch, StringArray,
+    TimestampMillisecondArray,
+};
+use arrow_schema::{DataType, Field, Schema, TimeUnit};
+use parquet::arrow::ArrowWriter;
+use parquet::basic::Compression;
+use parquet::file::properties::WriterProperties;
+use serde_json::{Map, Value};
+use std::sync::Arc;
+use tracing::{event, instrument, Level};
+
+use crate::storage;
+
+/// Name of the record timestamp attribute (milliseconds since the epoch).
+const TIMESTAMP_KEY: &str = "timestamp";
+
+/// String attributes every `NewRelicLogItem` carries (as serialized).
+const LOG_ITEM_KEYS: [&str; 6] = [
+    "loggerName",
+    "requestId",
+    "logtype",
+    "message",
+    "messageId",
+    "project",
+];
+
+/// Column type inferred from the values of a dynamic attribute.
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum ColumnKind {
+    Int,
+    Float,
+    Bool,
+    Text,
+}
+
+impl ColumnKind {
+    /// Returns the kind of a single JSON value (`None` for nulls).
+    fn of(value: &Value) -> Option<ColumnKind> {
+        match value {
+            Value::Null => None,
+            Value::Bool(_) => Some(ColumnKind::Bool),
+            Value::Number(n) if n.is_i64() => Some(ColumnKind::Int),
+            Value::Number(_) => Some(ColumnKind::Float),
+            _ => Some(ColumnKind::Text),
+        }
+    }
+
+    /// Widens the kind so it can hold values of both kinds.
+    fn merge(self, other: ColumnKind) -> ColumnKind {
+        match (self, other) {
+            (a, b) if a == b => a,
+            (ColumnKind::Int, ColumnKind::Float) | (ColumnKind::Float, ColumnKind::Int) => {
+                ColumnKind::Float
+            }
+            _ => ColumnKind::Text,
+        }
+    }
+
+    fn data_type(self) -> DataType {
+        match self {
+            ColumnKind::Int => DataType::Int64,
+            ColumnKind::Float => DataType::Float64,
+            ColumnKind::Bool => DataType::Boolean,
+            ColumnKind::Text => DataType::Utf8,
+        }
+    }
+}
+
+/// Reads the timestamp (milliseconds) of a record.
+fn timestamp_of(record: &Map<String, Value>) -> i64 {
+    record
+        .get(TIMESTAMP_KEY)
+        .and_then(|t| t.as_i64())
+        .unwrap_or(0)
+}
+
+/// Renders a JSON value as text for string columns.
+fn as_text(value: &Value) -> Option<String> {
+    match value {
+        Value::Null => None,
+        Value::String(s) => Some(s.to_owned()),
+        other => Some(other.to_string()),
+    }
+}
+
+/// Builds the arrow column for the given attribute across all records.
+fn build_column(records: &[Map<String, Value>], key: &str, kind: ColumnKind) -> ArrayRef {
+    let values = records.iter().map(|r| r.get(key).unwrap_or(&Value::Null));
+    match kind {
+        ColumnKind::Int => Arc::new(values.map(|v| v.as_i64()).collect::<Int64Array>()),
+        ColumnKind::Float => Arc::new(values.map(|v| v.as_f64()).collect::<Float64Array>()),
+        ColumnKind::Bool => Arc::new(values.map(|v| v.as_bool()).collect::<BooleanArray>()),
+        ColumnKind::Text => Arc::new(values.map(as_text).collect::<StringArray>()),
+    }
+}
+
+/// Converts the records into a Parquet file held in memory.
+fn to_parquet(records: &[Map<String, Value>]) -> Result<Vec<u8>, String> {
+    // infer the dynamic attribute columns in order of first appearance
+    let mut dynamic_columns: Vec<(String, ColumnKind)> = Vec::new();
+    for record in records {
+        for (key, value) in record {
+            if key == TIMESTAMP_KEY || LOG_ITEM_KEYS.contains(&key.as_str()) {
+                continue;
+            }
+            let Some(kind) = ColumnKind::of(value) else {
+                continue;
+            };
+            match dynamic_columns.iter_mut().find(|(k, _)| k == key) {
+                Some(column) => column.1 = column.1.merge(kind),
+                None => dynamic_columns.push((key.to_owned(), kind)),
+            }
+        }
+    }
+
+    let mut fields = vec![Field::new(
+        TIMESTAMP_KEY,
+        DataType::Timestamp(TimeUnit::Millisecond, Some("UTC".into())),
+        false,
+    )];
+    let timestamps = records.iter().map(timestamp_of).collect::<Vec<i64>>();
+    let mut columns: Vec<ArrayRef> = vec![Arc::new(
+        TimestampMillisecondArray::from(timestamps).with_timezone("UTC"),
+    )];
+
+    let static_columns = LOG_ITEM_KEYS
+        .iter()
+        .map(|k| (k.to_string(), ColumnKind::Text));
+    for (key, kind) in static_columns.chain(dynamic_columns) {
+        fields.push(Field::new(&key, kind.data_type(), true));
+        columns.push(build_column(records, &key, kind));
+    }
+
+    let schema = Arc::new(Schema::new(fields));
+    let batch = RecordBatch::try_new(schema.clone(), columns).map_err(|e| format!("{e:?}"))?;
+
+    let props = WriterProperties::builder()
+        .set_compression(Compression::SNAPPY)
+        .build();
+    let mut buffer: Vec<u8> = Vec::new();
+    let mut writer =
+        ArrowWriter::try_new(&mut buffer, schema, Some(props)).map_err(|e| format!("{e:?}"))?;
+    writer.write(&batch).map_err(|e| format!("{e:?}"))?;
+    writer.close().map_err(|e| format!("{e:?}"))?;
+    Ok(buffer)
+}
+
+/// Exports the records of the given files to Parquet, keeping only the
+/// records with a timestamp (in milliseconds) within the optional range.
+/// Exports every stored file when no filenames are given.
+#[instrument(name = "export_parquet")]
+pub async fn export_parquet(
+    filenames: &[String],
+    from: Option<i64>,
+    to: Option<i64>,
+) -> Result<Vec<u8>, String> {
+    let store = storage::global();
+    let filenames = if filenames.is_empty() {
+        store.get_log_filenames().await
+    } else {
+        filenames.to_vec()
+    };
+
+    let mut records: Vec<Map<String, Value>> = Vec::new();
+    for filename in filenames {
+        let lines = store
+            .get_all_lines(&filename)
+            .await
+            .map_err(|e| format!("Unable to read {filename}: {e:?}"))?;
+
+        for line in lines {
+            let Ok(record) = serde_json::from_str::<Map<String, Value>>(line.trim_end()) else {
+                event!(Level::WARN, "Skipping unreadable line in {filename}");
+                continue;
+            };
+            let timestamp = timestamp_of(&record);
+            if from.is_some_and(|f| timestamp < f) || to.is_some_and(|t| timestamp > t) {
+                continue;
+            }
+            records.push(record);
+        }
+    }
+
+    records.sort_by_key(timestamp_of);
+    event!(
+        Level::INFO,
+        "Exporting {} records to parquet",
+        records.len()
+    );
+    to_parquet(&records)
+}
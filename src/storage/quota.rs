@@ -0,0 +1,331 @@
+//! # Quota Module
+//!
+//! Protects the storage area from filling up.
+//!
+//! ## Path
+//!
+//! storage/quota.rs
+//!
+//! # Description
+//!
+//! Checks the size of `LOG_DIRECTORY` against `LS_QUOTA_BYTES` and the space
+//! left on its disk against `LS_MIN_FREE_BYTES` before each sync. When either
+//! limit is exceeded the configured `LS_QUOTA_POLICY` is applied:
+//!
+//! - `pause`: (default) skip syncing until there's room again
+//! - `evict`: delete the oldest files until the limits are met
+//! - `compress`: gzip closed files (rolled over or from a previous day)
+//!
+//! Scraping is paused whenever the policy can't free enough space, so the
+//! watermark stays put and no logs are fetched that can't be stored.
+//!
+//! The `gc` command applies the `evict` or `compress` policy right away
+//! (see `collect`), regardless of the configured one.
+//!
+//! ## Notes
+//!
+//! The `evict` and `compress` policies only apply to the `files` storage
+//! backend (deleted rows of the `sqlite` backend only free disk space once
+//! the database is vacuumed). Eviction only counts what deleting log files
+//! frees: when the search index, hash chains or checkpoints alone exceed the
+//! quota, or a deletion doesn't lower the usage, it stops instead of
+//! deleting every file.
+
+use serde::Serialize;
+use std::path::Path;
+use std::sync::Mutex;
+use tracing::{info, instrument, warn};
+
+use crate::env_config::{
+    EnvConfig, LOG_DIRECTORY, LS_MIN_FREE_BYTES, LS_QUOTA_BYTES, LS_QUOTA_POLICY,
+    LS_STORAGE_BACKEND,
+};
+use crate::storage::file_store::GZIP_EXTENSION;
+use crate::storage::{self, archive, file_lock, hash_chain, search_index};
+
+/// The storage usage and whether it's within the configured limits.
+#[derive(Serialize, Clone, Debug)]
+pub struct QuotaStatus {
+    pub ok: bool,
+    pub policy: String,
+    pub used_bytes: u64,
+    pub quota_bytes: Option<u64>,
+    pub available_bytes: Option<u64>,
+    pub min_free_bytes: Option<u64>,
+    pub message: String,
+}
+
+/// The storage usage measured last (i.e. before the last sync).
+static LAST_STATUS: Mutex<Option<QuotaStatus>> = Mutex::new(None);
+
+/// Reads a byte limit setting, where `0` (or an invalid value) disables it.
+fn get_limit(key: &str) -> Option<u64> {
+    let value = EnvConfig::global().get_val(key);
+    match value.parse::<u64>() {
+        Ok(0) => None,
+        Ok(limit) => Some(limit),
+        Err(_) => {
+            warn!("Ignoring invalid value '{value}' for {key}");
+            None
+        }
+    }
+}
+
+/// Recursively sums the size of the files under the given folder.
+fn directory_size(dir: &Path) -> u64 {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return 0;
+    };
+    entries
+        .flatten()
+        .map(|entry| match entry.metadata() {
+            Ok(m) if m.is_dir() => directory_size(&entry.path()),
+            Ok(m) => m.len(),
+            Err(_) => 0,
+        })
+        .sum()
+}
+
+/// Returns the storage usage measured last, only measuring it when it
+/// wasn't yet. Cheap enough for health checks.
+pub async fn last_status() -> QuotaStatus {
+    let last = LAST_STATUS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .clone();
+    match last {
+        Some(last) => last,
+        None => status().await,
+    }
+}
+
+/// Measures the storage usage against the configured limits.
+pub async fn status() -> QuotaStatus {
+    let current = measure().await;
+    *LAST_STATUS.lock().unwrap_or_else(|e| e.into_inner()) = Some(current.clone());
+    current
+}
+
+async fn measure() -> QuotaStatus {
+    let dir = EnvConfig::global().get_val(LOG_DIRECTORY);
+    let quota_bytes = get_limit(LS_QUOTA_BYTES);
+    let min_free_bytes = get_limit(LS_MIN_FREE_BYTES);
+
+    let used_bytes = {
+        let dir = dir.clone();
+        tokio::task::spawn_blocking(move || directory_size(Path::new(&dir)))
+            .await
+            .unwrap_or(0)
+    };
+    let available_bytes = fs4::available_space(&dir).ok();
+
+    let mut problems: Vec<String> = Vec::new();
+    if let Some(quota) = quota_bytes.filter(|q| used_bytes > *q) {
+        problems.push(format!(
+            "{used_bytes} bytes used exceeds the quota of {quota} bytes"
+        ));
+    }
+    if let (Some(min_free), Some(available)) = (min_free_bytes, available_bytes) {
+        if available < min_free {
+            problems.push(format!(
+                "{available} bytes free is below the minimum of {min_free} bytes"
+            ));
+        }
+    }
+
+    QuotaStatus {
+        ok: problems.is_empty(),
+        policy: EnvConfig::global().get_val(LS_QUOTA_POLICY),
+        used_bytes,
+        quota_bytes,
+        available_bytes,
+        min_free_bytes,
+        message: if problems.is_empty() {
+            "Storage is within limits".to_owned()
+        } else {
+            problems.join(", ")
+        },
+    }
+}
+
+/// Checks the limits before writing and applies the configured policy when
+/// they're exceeded. Returns the reason scraping should pause when there
+/// still isn't enough room afterwards.
+#[instrument(name = "ensure_capacity")]
+pub async fn ensure_capacity() -> Result<(), String> {
+    let current = status().await;
+    if current.ok {
+        return Ok(());
+    }
+    warn!("Storage is under pressure: {}", current.message);
+
+    apply_policy(&current.policy, current.message)
+        .await
+        .map_err(|reason| format!("Scraping is paused: {reason}"))
+}
+
+/// Frees up storage right away when the limits are exceeded, using the given
+/// policy instead of the configured one (i.e. via the `gc` command). Returns
+/// the storage usage afterwards.
+#[instrument(name = "collect_storage")]
+pub async fn collect(policy: Option<&str>) -> Result<QuotaStatus, String> {
+    let current = status().await;
+    if !current.ok {
+        let policy = policy.unwrap_or(&current.policy).to_owned();
+        info!(
+            "Storage is under pressure, applying the {policy} policy: {}",
+            current.message
+        );
+        apply_policy(&policy, current.message).await?;
+    }
+    Ok(status().await)
+}
+
+/// Applies the policy, failing with the reason when the limits still aren't met.
+async fn apply_policy(policy: &str, message: String) -> Result<(), String> {
+    match policy {
+        "pause" => Err(message),
+        "evict" => evict_oldest().await,
+        "compress" => compress_closed().await,
+        other => Err(format!("Unknown quota policy '{other}'")),
+    }
+}
+
+/// Deletes the oldest local files until the limits are met, stopping when
+/// deleting log files can't free enough.
+async fn evict_oldest() -> Result<(), String> {
+    if EnvConfig::global().get_val(LS_STORAGE_BACKEND) != "files" {
+        return Err("Eviction is only supported by the files storage backend".to_owned());
+    }
+
+    let mut files = storage::get_file_infos()
+        .await
+        .into_iter()
+        .filter(|f| f.local)
+        .collect::<Vec<storage::FileInfo>>();
+    files.sort_by(|a, b| {
+        (a.max_timestamp, a.modified, &a.name).cmp(&(b.max_timestamp, b.modified, &b.name))
+    });
+
+    // the search index, hash chains and checkpoints don't shrink by evicting
+    let mut current = status().await;
+    let evictable: u64 = files.iter().map(|f| f.size).sum();
+    if let Some(quota) = current.quota_bytes {
+        let other = current.used_bytes.saturating_sub(evictable);
+        if other > quota {
+            return Err(format!(
+                "{other} bytes used besides log files exceeds the quota of {quota} bytes, evicting can't free enough"
+            ));
+        }
+    }
+
+    for file in files {
+        storage::delete_file(&file.name)
+            .await
+            .map_err(|e| format!("Unable to evict {}: {e:?}", file.name))?;
+        info!(
+            "Evicted {} ({} bytes) to free up storage",
+            file.name, file.size
+        );
+
+        let previous = current;
+        current = status().await;
+        if current.ok {
+            return Ok(());
+        }
+        let freed = current.used_bytes < previous.used_bytes
+            || current.available_bytes > previous.available_bytes;
+        if !freed {
+            return Err(format!(
+                "Evicting {} didn't free up storage: {}",
+                file.name, current.message
+            ));
+        }
+    }
+    Err(current.message)
+}
+
+/// Compresses closed files until the limits are met.
+async fn compress_closed() -> Result<(), String> {
+    if EnvConfig::global().get_val(LS_STORAGE_BACKEND) != "files" {
+        return Err("Compression is only supported by the files storage backend".to_owned());
+    }
+
+    let mut filenames = storage::global().get_log_filenames().await;
+    filenames.sort();
+    for filename in filenames {
+        if filename.ends_with(GZIP_EXTENSION) || !archive::is_closed(&filename).await {
+            continue;
+        }
+        compress_file(&filename)
+            .await
+            .map_err(|e| format!("Unable to compress {filename}: {e:?}"))?;
+
+        let current = status().await;
+        if current.ok {
+            return Ok(());
+        }
+    }
+    Err(status().await.message)
+}
+
+/// Gzip-compresses a file into `{filename}.gz`, moving its hash chain and
+/// search index entries along with it. Returns the new filename. Fails
+/// rather than overwriting an existing compressed file or its chain.
+pub async fn compress_file(filename: &str) -> tokio::io::Result<String> {
+    let _lock = file_lock::acquire(filename).await?;
+    let compressed_name = format!("{filename}{GZIP_EXTENSION}");
+    let dir = EnvConfig::global().get_val(LOG_DIRECTORY);
+    let path = Path::new(&dir).join(filename);
+    let compressed_path = Path::new(&dir).join(&compressed_name);
+    if compressed_path.exists() || hash_chain::has_chain(&compressed_name) {
+        return Err(tokio::io::Error::new(
+            tokio::io::ErrorKind::AlreadyExists,
+            format!("{compressed_name} already exists"),
+        ));
+    }
+
+    let data = tokio::fs::read(&path).await?;
+    let original_size = data.len();
+    let compressed = tokio::task::spawn_blocking(move || {
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::best());
+        std::io::Write::write_all(&mut encoder, &data)?;
+        encoder.finish()
+    })
+    .await
+    .map_err(tokio::io::Error::other)??;
+
+    // write via a hidden temp file so a partial file is never listed
+    let tmp_path = compressed_path.with_file_name(format!(
+        ".{}.tmp",
+        compressed_path
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+    ));
+    tokio::fs::write(&tmp_path, &compressed).await?;
+    // linking (unlike renaming) fails when the target exists
+    let linked = tokio::fs::hard_link(&tmp_path, &compressed_path).await;
+    tokio::fs::remove_file(&tmp_path).await?;
+    linked?;
+    tokio::fs::remove_file(&path).await?;
+    info!(
+        "Compressed {filename} from {original_size} to {} bytes",
+        compressed.len()
+    );
+
+    hash_chain::rename(filename, &compressed_name).await?;
+    if search_index::global().is_some() {
+        let lines = storage::global().get_all_lines(&compressed_name).await?;
+        let (old_name, new_name) = (filename.to_owned(), compressed_name.clone());
+        let reindexed = search_index::run(move |index| {
+            index
+                .remove_file(&old_name)
+                .and_then(|_| index.index_lines(&new_name, 1, &lines))
+        });
+        if let Some(Err(err)) = reindexed.await {
+            warn!("Unable to move {filename} to {compressed_name} in the search index: {err}");
+        }
+    }
+    Ok(compressed_name)
+}
@@ -0,0 +1,197 @@
+//! # S3 Module
+//!
+//! A minimal client for S3-compatible object storage.
+//!
+//! ## Path
+//!
+//! storage/s3.rs
+//!
+//! # Description
+//!
+//! Uploads and downloads objects using path-style URLs
+//! (`{endpoint}/{bucket}/{key}`) signed with AWS Signature Version 4, which
+//! works with AWS S3 as well as MinIO and other S3-compatible services.
+
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use reqwest::header::{HeaderMap, HeaderValue};
+use reqwest::{Method, Url};
+use sha2::{Digest, Sha256};
+use tracing::{instrument, trace};
+
+use crate::env_config::{
+    EnvConfig, S3_ACCESS_KEY_ID, S3_BUCKET, S3_ENDPOINT, S3_REGION, S3_SECRET_ACCESS_KEY,
+};
+
+/// Returns the lowercase hex SHA-256 digest of the data.
+fn sha256_hex(data: &[u8]) -> String {
+    hex::encode(Sha256::digest(data))
+}
+
+/// Returns the HMAC-SHA256 of the data using the given key.
+fn hmac_sha256(key: &[u8], data: &str) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any size");
+    mac.update(data.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// URI-encodes each segment of an object key as required by SigV4.
+fn encode_key(key: &str) -> String {
+    key.split('/')
+        .map(|segment| {
+            segment
+                .bytes()
+                .map(|b| match b {
+                    b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                        (b as char).to_string()
+                    }
+                    _ => format!("%{b:02X}"),
+                })
+                .collect::<String>()
+        })
+        .collect::<Vec<String>>()
+        .join("/")
+}
+
+#[derive(Debug)]
+pub struct S3Client {
+    endpoint: String,
+    region: String,
+    bucket: String,
+    access_key_id: String,
+    secret_access_key: String,
+}
+
+impl S3Client {
+    /// Creates a new `S3Client` struct from the `S3_*` configuration values.
+    /// Falls back to the AWS endpoint of the region when no endpoint is set.
+    pub fn new() -> Result<S3Client, String> {
+        let env = EnvConfig::global();
+        let region = env.get_val(S3_REGION);
+        let bucket = env.get_val(S3_BUCKET);
+        if bucket.is_empty() {
+            return Err("No S3 bucket provided!".to_owned());
+        }
+
+        let configured_endpoint = env.get_val(S3_ENDPOINT);
+        let endpoint = if configured_endpoint.is_empty() {
+            format!("https://s3.{region}.amazonaws.com")
+        } else {
+            configured_endpoint.trim_end_matches('/').to_owned()
+        };
+
+        Ok(S3Client {
+            endpoint,
+            region,
+            bucket,
+            access_key_id: env.get_val(S3_ACCESS_KEY_ID),
+            secret_access_key: env.get_val(S3_SECRET_ACCESS_KEY),
+        })
+    }
+
+    /// Name of the bucket objects are stored in.
+    pub fn bucket(&self) -> &str {
+        &self.bucket
+    }
+
+    /// Builds the path-style URL of an object.
+    fn object_url(&self, key: &str) -> Result<Url, String> {
+        let url = format!("{}/{}/{}", self.endpoint, self.bucket, encode_key(key));
+        Url::parse(&url).map_err(|e| format!("Invalid S3 url {url}: {e:?}"))
+    }
+
+    /// Creates the SigV4 headers for a request with the given payload hash.
+    fn sign(
+        &self,
+        method: &Method,
+        url: &Url,
+        payload_hash: &str,
+        now: DateTime<Utc>,
+    ) -> Result<HeaderMap, String> {
+        let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+        let date = now.format("%Y%m%d").to_string();
+        let host = match (url.host_str(), url.port()) {
+            (Some(h), Some(p)) => format!("{h}:{p}"),
+            (Some(h), None) => h.to_owned(),
+            _ => return Err(format!("Invalid S3 host in {url}")),
+        };
+
+        let signed_headers = "host;x-amz-content-sha256;x-amz-date";
+        let canonical_request = format!(
+            "{method}\n{}\n\nhost:{host}\nx-amz-content-sha256:{payload_hash}\nx-amz-date:{amz_date}\n\n{signed_headers}\n{payload_hash}",
+            url.path()
+        );
+        let scope = format!("{date}/{}/s3/aws4_request", self.region);
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{amz_date}\n{scope}\n{}",
+            sha256_hex(canonical_request.as_bytes())
+        );
+
+        let k_date = hmac_sha256(format!("AWS4{}", self.secret_access_key).as_bytes(), &date);
+        let k_region = hmac_sha256(&k_date, &self.region);
+        let k_service = hmac_sha256(&k_region, "s3");
+        let k_signing = hmac_sha256(&k_service, "aws4_request");
+        let signature = hex::encode(hmac_sha256(&k_signing, &string_to_sign));
+
+        let authorization = format!(
+            "AWS4-HMAC-SHA256 Credential={}/{scope}, SignedHeaders={signed_headers}, Signature={signature}",
+            self.access_key_id
+        );
+
+        let mut headers = HeaderMap::new();
+        let header = |v: &str| HeaderValue::from_str(v).map_err(|e| format!("{e:?}"));
+        headers.insert("x-amz-date", header(&amz_date)?);
+        headers.insert("x-amz-content-sha256", header(payload_hash)?);
+        headers.insert("authorization", header(&authorization)?);
+        Ok(headers)
+    }
+
+    /// Uploads the data as the object with the given key.
+    #[instrument(name = "s3_put_object", skip(data))]
+    pub async fn put_object(&self, key: &str, data: Vec<u8>) -> Result<(), String> {
+        let url = self.object_url(key)?;
+        let headers = self.sign(&Method::PUT, &url, &sha256_hex(&data), Utc::now())?;
+        trace!("Uploading {} bytes to {url}", data.len());
+
+        let response = reqwest::Client::new()
+            .put(url)
+            .headers(headers)
+            .body(data)
+            .send()
+            .await
+            .map_err(|e| format!("Failed to upload {key}: {e:?}"))?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let body = response.text().await.unwrap_or_default();
+            return Err(format!("Failed to upload {key} ({status}): {body}"));
+        }
+        Ok(())
+    }
+
+    /// Downloads the object with the given key.
+    #[instrument(name = "s3_get_object")]
+    pub async fn get_object(&self, key: &str) -> Result<Vec<u8>, String> {
+        let url = self.object_url(key)?;
+        let headers = self.sign(&Method::GET, &url, &sha256_hex(b""), Utc::now())?;
+        trace!("Downloading {url}");
+
+        let response = reqwest::Client::new()
+            .get(url)
+            .headers(headers)
+            .send()
+            .await
+            .map_err(|e| format!("Failed to download {key}: {e:?}"))?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let body = response.text().await.unwrap_or_default();
+            return Err(format!("Failed to download {key} ({status}): {body}"));
+        }
+        let bytes = response
+            .bytes()
+            .await
+            .map_err(|e| format!("Failed to read {key}: {e:?}"))?;
+        Ok(bytes.to_vec())
+    }
+}
@@ -0,0 +1,331 @@
+//! # Search Index Module
+//!
+//! Maintains a full-text index over stored log lines.
+//!
+//! ## Path
+//!
+//! storage/search_index.rs
+//!
+//! # Description
+//!
+//! Uses a `tantivy` inverted index to make the contents of stored logs
+//! searchable. Lines are indexed as the storage module writes them, keeping
+//! a reference to the filename and line number they were written to so
+//! results can be found again via the logs API.
+//!
+//! ## Notes
+//!
+//! Only enabled when `LS_SEARCH_ENABLED` is `true` (off by default). Defines
+//! an `INDEX` instance to be set only once on app start (see `init`).
+//! When the index starts out empty the server rebuilds it from the storage
+//! backend (see `start_rebuild`), one-off commands never do.
+
+use once_cell::sync::OnceCell;
+use serde::Serialize;
+use std::ops::Bound;
+use std::path::Path;
+use std::sync::Mutex;
+use tantivy::collector::{Count, TopDocs};
+use tantivy::directory::MmapDirectory;
+use tantivy::query::{BooleanQuery, Occur, Query, QueryParser, RangeQuery};
+use tantivy::schema::{Field, Schema, Value, INDEXED, STORED, STRING, TEXT};
+use tantivy::{
+    doc, Index, IndexReader, IndexWriter, ReloadPolicy, SnippetGenerator, TantivyDocument, Term,
+};
+use tracing::{event, instrument, Level};
+
+use crate::env_config::{EnvConfig, LOG_DIRECTORY, LS_SEARCH_ENABLED, LS_SEARCH_INDEX_PATH};
+use crate::storage;
+
+/// The cell to init and hold the search index instance (only writable once).
+static INDEX: OnceCell<SearchIndex> = OnceCell::new();
+
+/// Folder name of the index when `LS_SEARCH_INDEX_PATH` is not set.
+const DEFAULT_INDEX_DIR: &str = ".search_index";
+
+/// Memory budget of the index writer.
+const WRITER_HEAP_BYTES: usize = 50_000_000;
+
+/// A single line matching a search query.
+#[derive(Serialize, Debug)]
+pub struct SearchHit {
+    pub filename: String,
+    pub line: u64,
+    pub timestamp: i64,
+    pub content: String,
+    pub highlight: String,
+}
+
+/// Handles to the fields of the index schema.
+#[derive(Debug)]
+struct IndexFields {
+    filename: Field,
+    line: Field,
+    timestamp: Field,
+    message: Field,
+    content: Field,
+}
+
+/// Full-text index over stored log lines.
+pub struct SearchIndex {
+    index: Index,
+    reader: IndexReader,
+    writer: Mutex<IndexWriter>,
+    fields: IndexFields,
+}
+
+impl std::fmt::Debug for SearchIndex {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SearchIndex")
+            .field("fields", &self.fields)
+            .finish()
+    }
+}
+
+/// Resolves the index location, falling back to a folder under `LOG_DIRECTORY`.
+fn get_index_path() -> String {
+    let env = EnvConfig::global();
+    let configured = env.get_val(LS_SEARCH_INDEX_PATH);
+    if !configured.is_empty() {
+        return configured;
+    }
+    Path::new(&env.get_val(LOG_DIRECTORY))
+        .join(DEFAULT_INDEX_DIR)
+        .to_string_lossy()
+        .into_owned()
+}
+
+impl SearchIndex {
+    /// Opens (or creates) the index at the given location.
+    pub fn open(index_path: &str) -> Result<SearchIndex, String> {
+        let mut schema_builder = Schema::builder();
+        let fields = IndexFields {
+            filename: schema_builder.add_text_field("filename", STRING | STORED),
+            line: schema_builder.add_u64_field("line", STORED),
+            timestamp: schema_builder.add_i64_field("timestamp", INDEXED | STORED),
+            message: schema_builder.add_text_field("message", TEXT | STORED),
+            content: schema_builder.add_text_field("content", TEXT | STORED),
+        };
+        let schema = schema_builder.build();
+
+        std::fs::create_dir_all(index_path).map_err(|e| format!("{e:?}"))?;
+        let directory = MmapDirectory::open(index_path).map_err(|e| format!("{e:?}"))?;
+        let index = Index::open_or_create(directory, schema).map_err(|e| format!("{e:?}"))?;
+        let writer = index
+            .writer(WRITER_HEAP_BYTES)
+            .map_err(|e| format!("{e:?}"))?;
+        let reader = index
+            .reader_builder()
+            .reload_policy(ReloadPolicy::Manual)
+            .try_into()
+            .map_err(|e| format!("{e:?}"))?;
+
+        Ok(SearchIndex {
+            index,
+            reader,
+            writer: Mutex::new(writer),
+            fields,
+        })
+    }
+
+    /// Determines whether the index holds any lines yet.
+    pub fn is_empty(&self) -> bool {
+        self.reader.searcher().num_docs() == 0
+    }
+
+    /// Adds the given lines of a file to the index, numbering them from `first_line`.
+    pub fn index_lines(
+        &self,
+        filename: &str,
+        first_line: u64,
+        lines: &[String],
+    ) -> Result<(), String> {
+        let mut writer = self.writer.lock().map_err(|e| format!("{e:?}"))?;
+        for (i, raw) in lines.iter().enumerate() {
+            let content = raw.trim_end();
+            if content.is_empty() {
+                continue;
+            }
+
+            // pull out the message and timestamp of the record when possible
+            let record = serde_json::from_str::<serde_json::Value>(content).unwrap_or_default();
+            let message = record["message"].as_str().unwrap_or("");
+            let timestamp = record["timestamp"].as_i64().unwrap_or(0);
+
+            writer
+                .add_document(doc!(
+                    self.fields.filename => filename,
+                    self.fields.line => first_line + i as u64,
+                    self.fields.timestamp => timestamp,
+                    self.fields.message => message,
+                    self.fields.content => content,
+                ))
+                .map_err(|e| format!("{e:?}"))?;
+        }
+        self.commit(&mut writer)
+    }
+
+    /// Removes all lines of a file from the index.
+    pub fn remove_file(&self, filename: &str) -> Result<(), String> {
+        let mut writer = self.writer.lock().map_err(|e| format!("{e:?}"))?;
+        writer.delete_term(Term::from_field_text(self.fields.filename, filename));
+        self.commit(&mut writer)
+    }
+
+    fn commit(&self, writer: &mut IndexWriter) -> Result<(), String> {
+        writer.commit().map_err(|e| format!("{e:?}"))?;
+        self.reader.reload().map_err(|e| format!("{e:?}"))
+    }
+
+    /// Searches the message and contents of indexed lines, optionally limited
+    /// to records with a timestamp (in milliseconds) in the given range.
+    /// Returns the total number of matches along with the top `limit` hits.
+    #[instrument(name = "search")]
+    pub fn search(
+        &self,
+        query: &str,
+        from: Option<i64>,
+        to: Option<i64>,
+        limit: usize,
+    ) -> Result<(usize, Vec<SearchHit>), String> {
+        let parser =
+            QueryParser::for_index(&self.index, vec![self.fields.message, self.fields.content]);
+        let text_query = parser
+            .parse_query(query)
+            .map_err(|e| format!("Invalid search query: {e}"))?;
+
+        let mut search_query: Box<dyn Query> = text_query.box_clone();
+        if from.is_some() || to.is_some() {
+            let range = RangeQuery::new_i64_bounds(
+                "timestamp".to_owned(),
+                from.map_or(Bound::Unbounded, Bound::Included),
+                to.map_or(Bound::Unbounded, Bound::Included),
+            );
+            search_query = Box::new(BooleanQuery::new(vec![
+                (Occur::Must, search_query),
+                (Occur::Must, Box::new(range)),
+            ]));
+        }
+
+        let searcher = self.reader.searcher();
+        let (top_docs, total) = searcher
+            .search(&search_query, &(TopDocs::with_limit(limit.max(1)), Count))
+            .map_err(|e| format!("{e:?}"))?;
+        let snippets = SnippetGenerator::create(&searcher, &*text_query, self.fields.message)
+            .map_err(|e| format!("{e:?}"))?;
+
+        let mut hits = Vec::new();
+        for (_score, address) in top_docs {
+            let found: TantivyDocument = searcher.doc(address).map_err(|e| format!("{e:?}"))?;
+            let text = |field: Field| {
+                found
+                    .get_first(field)
+                    .and_then(|v| v.as_str())
+                    .unwrap_or("")
+                    .to_owned()
+            };
+            hits.push(SearchHit {
+                filename: text(self.fields.filename),
+                line: found
+                    .get_first(self.fields.line)
+                    .and_then(|v| v.as_u64())
+                    .unwrap_or(0),
+                timestamp: found
+                    .get_first(self.fields.timestamp)
+                    .and_then(|v| v.as_i64())
+                    .unwrap_or(0),
+                content: text(self.fields.content),
+                highlight: snippets.snippet_from_doc(&found).to_html(),
+            });
+        }
+        Ok((total, hits))
+    }
+}
+
+/// Opens the search index if enabled via the `LS_SEARCH_ENABLED` configuration.
+pub fn init() -> Result<(), String> {
+    if EnvConfig::global().get_val(LS_SEARCH_ENABLED) != "true" {
+        event!(Level::INFO, "Search index is disabled");
+        return Ok(());
+    }
+
+    let index_path = get_index_path();
+    event!(Level::INFO, "Opening search index at {index_path}");
+    let index = SearchIndex::open(&index_path)?;
+    if INDEX.set(index).is_err() {
+        event!(Level::WARN, "Search index was already initialized");
+    }
+    Ok(())
+}
+
+/// Rebuilds the search index in the background when it is empty.
+pub fn start_rebuild() {
+    if global().is_some_and(|index| index.is_empty()) {
+        tokio::spawn(rebuild());
+    }
+}
+
+/// Get the search index instance if search is enabled.
+pub fn global() -> Option<&'static SearchIndex> {
+    INDEX.get()
+}
+
+/// Runs the work with the search index on the blocking thread pool. Returns
+/// `None` when search is disabled.
+pub async fn run<T, F>(work: F) -> Option<Result<T, String>>
+where
+    T: Send + 'static,
+    F: FnOnce(&'static SearchIndex) -> Result<T, String> + Send + 'static,
+{
+    let index = global()?;
+    let result = tokio::task::spawn_blocking(move || work(index))
+        .await
+        .map_err(|e| format!("{e:?}"))
+        .and_then(|result| result);
+    Some(result)
+}
+
+/// Indexes every line currently held by the storage backend.
+///
+/// Each file is reindexed under its file lock, replacing whatever was
+/// indexed for it so far, so lines written while the rebuild runs are never
+/// indexed twice.
+#[instrument(name = "rebuild_search_index")]
+pub async fn rebuild() {
+    if global().is_none() {
+        return;
+    }
+    let store = storage::global();
+    for filename in store.get_log_filenames().await {
+        let _lock = match storage::file_lock::acquire(&filename).await {
+            Ok(lock) => lock,
+            Err(err) => {
+                event!(
+                    Level::ERROR,
+                    "Unable to lock {filename} for indexing: {err:?}"
+                );
+                continue;
+            }
+        };
+        let lines = match store.get_all_lines(&filename).await {
+            Ok(lines) => lines,
+            Err(err) => {
+                event!(
+                    Level::ERROR,
+                    "Unable to read {filename} for indexing: {err:?}"
+                );
+                continue;
+            }
+        };
+        let name = filename.clone();
+        let reindexed = run(move |index| {
+            index
+                .remove_file(&name)
+                .and_then(|_| index.index_lines(&name, 1, &lines))
+        });
+        if let Some(Err(err)) = reindexed.await {
+            event!(Level::ERROR, "Unable to index {filename}: {err}");
+        }
+    }
+    event!(Level::INFO, "Finished rebuilding the search index");
+}
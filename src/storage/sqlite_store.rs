@@ -0,0 +1,358 @@
+//! # SQLite Store Module
+//!
+//! Handles saving and reading log records to and from an embedded SQLite database.
+//!
+//! ## Path
+//!
+//! storage/sqlite_store.rs
+//!
+//! # Description
+//!
+//! Writes each log record as a row in the `logs` table, keyed by the same
+//! filename the file backend would have used so the logs API can list, page
+//! and delete them the same way. The record fields are stored in columns
+//! (indexed on timestamp, logtype, logger_name and request_id) alongside the
+//! raw JSON line for querying. When encryption is enabled the `message` and
+//! `raw` columns hold encrypted values.
+//!
+//! ## Notes
+//!
+//! Queries block while they run, so they're run on tokio's blocking thread
+//! pool (see `SqliteStore::run`) to keep them from stalling the runtime.
+//! The default database is a hidden file so it isn't served as a log file.
+
+use async_trait::async_trait;
+use rusqlite::{params, Connection};
+use std::cmp;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use tracing::{event, instrument, Level};
+
+use crate::env_config::{EnvConfig, LOG_DIRECTORY, LS_SQLITE_PATH};
+use crate::new_relic::types::NewRelicLogItem;
+use crate::storage::{encryption, FileInfo, LogStore};
+
+/// Filename of the database when `LS_SQLITE_PATH` is not set.
+const DEFAULT_DB_NAME: &str = ".log_scraper.db";
+
+/// Statements for creating the table and indices if they don't exist yet.
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS logs (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        filename TEXT NOT NULL,
+        timestamp INTEGER NOT NULL,
+        logtype TEXT NOT NULL,
+        logger_name TEXT NOT NULL,
+        request_id TEXT NOT NULL,
+        message_id TEXT NOT NULL,
+        project TEXT NOT NULL,
+        message TEXT NOT NULL,
+        raw TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS idx_logs_filename ON logs (filename, id);
+    CREATE INDEX IF NOT EXISTS idx_logs_timestamp ON logs (timestamp);
+    CREATE INDEX IF NOT EXISTS idx_logs_logtype ON logs (logtype);
+    CREATE INDEX IF NOT EXISTS idx_logs_logger_name ON logs (logger_name);
+    CREATE INDEX IF NOT EXISTS idx_logs_request_id ON logs (request_id);
+";
+
+/// Maps a SQLite error to an io error so callers can treat backends alike.
+fn to_io_error(err: rusqlite::Error) -> tokio::io::Error {
+    tokio::io::Error::other(err)
+}
+
+/// Resolves the database location, falling back to a file under `LOG_DIRECTORY`.
+pub(crate) fn get_db_path() -> String {
+    let env = EnvConfig::global();
+    let configured = env.get_val(LS_SQLITE_PATH);
+    if !configured.is_empty() {
+        return configured;
+    }
+    Path::new(&env.get_val(LOG_DIRECTORY))
+        .join(DEFAULT_DB_NAME)
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// Storage backend that keeps logs as rows in an embedded SQLite database.
+#[derive(Debug)]
+pub struct SqliteStore {
+    connection: Arc<Mutex<Connection>>,
+}
+
+impl SqliteStore {
+    /// Creates a new `SqliteStore` struct, opening (or creating) the database
+    /// and ensuring the schema exists.
+    pub fn new() -> tokio::io::Result<SqliteStore> {
+        let db_path = get_db_path();
+        if let Some(parent) = Path::new(&db_path).parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        event!(Level::INFO, "Opening SQLite database at {db_path}");
+        let connection = Connection::open(&db_path).map_err(to_io_error)?;
+        connection.execute_batch(SCHEMA).map_err(to_io_error)?;
+
+        Ok(SqliteStore {
+            connection: Arc::new(Mutex::new(connection)),
+        })
+    }
+
+    /// Runs the query with the connection on the blocking thread pool.
+    async fn run<T, F>(&self, query: F) -> tokio::io::Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> tokio::io::Result<T> + Send + 'static,
+    {
+        let connection = self.connection.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut conn = connection
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+            query(&mut conn)
+        })
+        .await
+        .map_err(tokio::io::Error::other)?
+    }
+}
+
+#[async_trait]
+impl LogStore for SqliteStore {
+    #[instrument(name = "get_log_filenames")]
+    async fn get_log_filenames(&self) -> Vec<String> {
+        let result = self
+            .run(|conn| {
+                conn.prepare("SELECT DISTINCT filename FROM logs ORDER BY filename")
+                    .and_then(|mut stmt| {
+                        stmt.query_map([], |row| row.get::<_, String>(0))?
+                            .collect::<rusqlite::Result<Vec<String>>>()
+                    })
+                    .map_err(to_io_error)
+            })
+            .await;
+
+        match result {
+            Ok(names) => names,
+            Err(err) => {
+                event!(
+                    Level::ERROR,
+                    "Error reading filenames from database: {err:?}"
+                );
+                Vec::new()
+            }
+        }
+    }
+
+    async fn has_file(&self, filename: &str) -> bool {
+        let filename = filename.to_owned();
+        self.run(move |conn| {
+            conn.query_row(
+                "SELECT EXISTS(SELECT 1 FROM logs WHERE filename = ?1)",
+                params![filename],
+                |row| row.get::<_, bool>(0),
+            )
+            .map_err(to_io_error)
+        })
+        .await
+        .unwrap_or(false)
+    }
+
+    async fn total_lines(&self, filename: &str) -> tokio::io::Result<usize> {
+        let filename = filename.to_owned();
+        self.run(move |conn| {
+            conn.query_row(
+                "SELECT COUNT(*) FROM logs WHERE filename = ?1",
+                params![filename],
+                |row| row.get::<_, usize>(0),
+            )
+            .map_err(to_io_error)
+        })
+        .await
+    }
+
+    async fn get_lines_by_page(
+        &self,
+        filename: &str,
+        page: u32,
+        lines_per_page: u32,
+    ) -> tokio::io::Result<Vec<String>> {
+        // ensure params have sane values
+        let normalized_page: u32 = cmp::max(1, page);
+        let normalized_max_lines: u32 = cmp::max(1, lines_per_page);
+        let offset = (normalized_page - 1) as u64 * normalized_max_lines as u64;
+
+        let filename = filename.to_owned();
+        let rows = self
+            .run(move |conn| {
+                let mut stmt = conn
+                    .prepare(
+                        "SELECT raw FROM logs WHERE filename = ?1 ORDER BY id LIMIT ?2 OFFSET ?3",
+                    )
+                    .map_err(to_io_error)?;
+                let rows = stmt
+                    .query_map(params![filename, normalized_max_lines, offset], |row| {
+                        row.get::<_, String>(0)
+                    })
+                    .map_err(to_io_error)?;
+                rows.collect::<rusqlite::Result<Vec<String>>>()
+                    .map_err(to_io_error)
+            })
+            .await?;
+        rows.iter()
+            .map(|raw| encryption::decrypt_line(raw))
+            .collect()
+    }
+
+    /// Reads the metadata from the rows of the file. Write times aren't
+    /// recorded, so created and modified are left empty.
+    async fn file_info(&self, filename: &str) -> tokio::io::Result<FileInfo> {
+        let filename = filename.to_owned();
+        self.run(move |conn| {
+            conn.query_row(
+                "SELECT COUNT(*), COALESCE(SUM(LENGTH(raw)), 0), MIN(timestamp), MAX(timestamp)
+                 FROM logs WHERE filename = ?1",
+                params![filename],
+                |row| {
+                    Ok(FileInfo {
+                        name: filename.clone(),
+                        lines: Some(row.get::<_, i64>(0)? as usize),
+                        size: row.get::<_, i64>(1)? as u64,
+                        min_timestamp: row.get(2)?,
+                        max_timestamp: row.get(3)?,
+                        ..FileInfo::default()
+                    })
+                },
+            )
+            .map_err(to_io_error)
+        })
+        .await
+    }
+
+    async fn get_last_lines(
+        &self,
+        filename: &str,
+        skip: usize,
+        count: usize,
+    ) -> tokio::io::Result<Vec<String>> {
+        let filename = filename.to_owned();
+        let rows = self
+            .run(move |conn| {
+                let mut stmt = conn
+                    .prepare("SELECT raw FROM logs WHERE filename = ?1 ORDER BY id DESC LIMIT ?2 OFFSET ?3")
+                    .map_err(to_io_error)?;
+                let rows = stmt
+                    .query_map(params![filename, count as u64, skip as u64], |row| {
+                        row.get::<_, String>(0)
+                    })
+                    .map_err(to_io_error)?;
+                rows.collect::<rusqlite::Result<Vec<String>>>()
+                    .map_err(to_io_error)
+            })
+            .await?;
+        rows.iter()
+            .map(|raw| encryption::decrypt_line(raw))
+            .collect()
+    }
+
+    async fn write_logs(&self, filename: &str, logs: &[NewRelicLogItem]) -> tokio::io::Result<()> {
+        let rows = logs
+            .iter()
+            .map(|l| {
+                Ok((
+                    l.timestamp.timestamp_millis(),
+                    l.logtype.clone(),
+                    l.logger_name.clone(),
+                    l.request_id.clone(),
+                    l.message_id.clone(),
+                    l.project.clone(),
+                    encryption::encrypt_line(&l.message)?,
+                    encryption::encrypt_line(&l.to_string())?,
+                ))
+            })
+            .collect::<tokio::io::Result<Vec<_>>>()?;
+
+        let filename = filename.to_owned();
+        self.run(move |conn| {
+            let tx = conn.transaction().map_err(to_io_error)?;
+            {
+                let mut stmt = tx
+                    .prepare(
+                        "INSERT INTO logs (filename, timestamp, logtype, logger_name, request_id, message_id, project, message, raw)
+                         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+                    )
+                    .map_err(to_io_error)?;
+                for (timestamp, logtype, logger_name, request_id, message_id, project, message, raw) in rows {
+                    stmt.execute(params![
+                        filename,
+                        timestamp,
+                        logtype,
+                        logger_name,
+                        request_id,
+                        message_id,
+                        project,
+                        message,
+                        raw,
+                    ])
+                    .map_err(to_io_error)?;
+                }
+            }
+            tx.commit().map_err(to_io_error)
+        })
+        .await
+    }
+
+    #[instrument(name = "delete_file")]
+    async fn delete_file(&self, filename: &str) -> tokio::io::Result<()> {
+        let filename = filename.to_owned();
+        self.run(move |conn| {
+            conn.execute("DELETE FROM logs WHERE filename = ?1", params![filename])
+                .map_err(to_io_error)?;
+            Ok(())
+        })
+        .await
+    }
+
+    #[instrument(name = "reencrypt_file")]
+    async fn reencrypt(&self, filename: &str) -> tokio::io::Result<usize> {
+        let filename = filename.to_owned();
+        self.run(move |conn| {
+            let tx = conn.transaction().map_err(to_io_error)?;
+            let mut rewritten = 0;
+            {
+                let mut select = tx
+                    .prepare("SELECT id, message, raw FROM logs WHERE filename = ?1 ORDER BY id")
+                    .map_err(to_io_error)?;
+                let rows = select
+                    .query_map(params![filename], |row| {
+                        Ok((
+                            row.get::<_, i64>(0)?,
+                            row.get::<_, String>(1)?,
+                            row.get::<_, String>(2)?,
+                        ))
+                    })
+                    .map_err(to_io_error)?
+                    .collect::<rusqlite::Result<Vec<(i64, String, String)>>>()
+                    .map_err(to_io_error)?;
+
+                let mut update = tx
+                    .prepare("UPDATE logs SET message = ?2, raw = ?3 WHERE id = ?1")
+                    .map_err(to_io_error)?;
+                for (id, message, raw) in rows {
+                    if !encryption::needs_reencrypt(&raw) {
+                        continue;
+                    }
+                    let reencrypt = |v: &str| {
+                        encryption::decrypt_line(v).and_then(|p| encryption::encrypt_line(&p))
+                    };
+                    update
+                        .execute(params![id, reencrypt(&message)?, reencrypt(&raw)?])
+                        .map_err(to_io_error)?;
+                    rewritten += 1;
+                }
+            }
+            tx.commit().map_err(to_io_error)?;
+            Ok(rewritten)
+        })
+        .await
+    }
+}